@@ -0,0 +1,306 @@
+use std::time::{Duration, Instant};
+use glam::*;
+use crate::{
+    math::{*,
+        direction::*,
+        transform::Transform,
+    },
+    game::Game,
+    player::PlayerMode,
+    input::InputState,
+    world::chunk::{StageTimings, ChunkStats},
+};
+
+// Flythrough benchmark -- the player follows a spline at a fixed speed
+// through a seeded world, one tick per frame so every run does the same work
+// per frame timings of the chunk stages (and the upload when there is a gpu)
+// are summarized as percentiles
+
+//{{{ Spline
+
+// uniform catmull-rom through the points, arc length parameterized
+pub struct Spline
+{
+    pub points: Vec<DVec3>,
+    pub closed: bool,
+    pub length: f64,
+    table: Vec<(f64, f64)>, // (distance, t)
+}
+
+impl Spline
+{
+    const SAMPLES_PER_SEGMENT: usize = 32;
+
+    pub fn new(points: Vec<DVec3>, closed: bool) -> Self
+    {
+        let mut spline = Self {points, closed, length: 0.0, table: vec![]};
+        let n = spline.segments() * Self::SAMPLES_PER_SEGMENT;
+        let mut prev = spline.eval(0.0);
+        spline.table.push((0.0, 0.0));
+        for i in 1 ..= n {
+            let t = i as f64 / n as f64 * spline.segments() as f64;
+            let p = spline.eval(t);
+            spline.length += p.distance(prev);
+            spline.table.push((spline.length, t));
+            prev = p;
+        }
+        spline
+    }
+
+    pub fn segments(&self) -> usize
+    {
+        if self.closed {self.points.len()} else {self.points.len().saturating_sub(1).max(1)}
+    }
+
+    fn point(&self, i: isize) -> DVec3
+    {
+        let n = self.points.len() as isize;
+        if self.closed {self.points[i.rem_euclid(n) as usize]}
+        else {self.points[i.clamp(0, n - 1) as usize]}
+    }
+
+    // t in 0..segments
+    pub fn eval(&self, t: f64) -> DVec3
+    {
+        let seg = (t.floor() as isize).min(self.segments() as isize - 1).max(0);
+        let u = t - seg as f64;
+        let (p0, p1, p2, p3) = (self.point(seg - 1), self.point(seg), self.point(seg + 1), self.point(seg + 2));
+        let (u2, u3) = (u * u, u * u * u);
+        0.5 * (2.0 * p1
+            + (p2 - p0) * u
+            + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * u2
+            + (3.0 * p1 - p0 - 3.0 * p2 + p3) * u3)
+    }
+
+    // position and direction of travel a distance along the path
+    pub fn at_distance(&self, distance: f64) -> (DVec3, DVec3)
+    {
+        let d = if self.closed {distance.rem_euclid(self.length)} else {distance.clamp(0.0, self.length)};
+        let i = self.table.partition_point(|(td, _)| *td < d).clamp(1, self.table.len() - 1);
+        let (d0, t0) = self.table[i - 1];
+        let (d1, t1) = self.table[i];
+        let t = if d1 > d0 {t0 + (t1 - t0) * (d - d0) / (d1 - d0)} else {t1};
+        let pos = self.eval(t);
+        let tangent = (self.eval((t + 1e-3).min(self.segments() as f64)) - self.eval((t - 1e-3).max(0.0))).normalize_or_zero();
+        (pos, tangent)
+    }
+
+    // closed loop around the origin, radius and height vary with the seed
+    pub fn flythrough(seed: u32, radius: f64, count: usize) -> Self
+    {
+        let mut state = seed as u64 ^ 0x9E37_79B9_7F4A_7C15;
+        let mut rand = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 11) as f64 / (1u64 << 53) as f64
+        };
+        let points = (0 .. count).map(|i| {
+            let a = i as f64 / count as f64 * std::f64::consts::TAU;
+            let r = radius * (0.6 + 0.8 * rand());
+            dvec3(a.cos() * r, (rand() - 0.5) * radius * 0.5, a.sin() * r)
+        }).collect();
+        Self::new(points, true)
+    }
+}
+
+//}}}
+
+//{{{ Benchmark
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameTimings
+{
+    pub frame: Duration,          // whole frame, including render when windowed
+    pub stages: StageTimings,
+    pub upload: Option<Duration>, // none when headless
+    pub stats: ChunkStats,        // at the end of the frame
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Summary
+{
+    pub mean: f64, // milliseconds
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Summary
+{
+    pub fn from_millis(mut values: Vec<f64>) -> Self
+    {
+        if values.is_empty() {return Self::default();}
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        // nearest rank
+        let rank = |p: f64| values[((p * values.len() as f64).ceil() as usize).clamp(1, values.len()) - 1];
+        Self {
+            mean: values.iter().sum::<f64>() / values.len() as f64,
+            p50: rank(0.50),
+            p95: rank(0.95),
+            p99: rank(0.99),
+            max: *values.last().unwrap(),
+        }
+    }
+}
+
+pub struct Benchmark
+{
+    pub path: Spline,
+    pub speed: f64,    // units per second
+    pub dt: f64,       // simulated seconds per frame
+    pub frames: usize,
+    pub distance: f64,
+    pub results: Vec<FrameTimings>,
+    pub frame_start: Instant,
+}
+
+impl Benchmark
+{
+
+    pub fn new(seed: u32, speed: f64, frames: usize) -> Self
+    {
+        Self {
+            path: Spline::flythrough(seed, 150.0, 12),
+            speed,
+            dt: 1.0 / 60.0,
+            frames,
+            distance: 0.0,
+            results: Vec::with_capacity(frames),
+            frame_start: Instant::now(),
+        }
+    }
+
+    // spectator without collision so the path is followed exactly
+    pub fn prepare(&mut self, game: &mut Game)
+    {
+        game.player.set_mode(PlayerMode::Spectator);
+        game.player.collider.enabled = false;
+        self.place(game);
+        // drop anything accumulated while loading
+        game.world.end_frame();
+        self.frame_start = Instant::now();
+    }
+
+    fn place(&self, game: &mut Game)
+    {
+        let (pos, dir) = self.path.at_distance(self.distance);
+        game.player.transform = Transform::new(pos, look_rotation(dir, DDirection::UP));
+    }
+
+    pub fn done(&self) -> bool { self.results.len() >= self.frames }
+
+    // one tick along the path
+    pub fn step(&mut self, game: &mut Game) -> Result<(), String>
+    {
+        self.distance += self.speed * self.dt;
+        self.place(game);
+        game.update(self.dt as f32, &InputState::default())
+    }
+
+    // end of frame, after rendering
+    pub fn record(&mut self, stages: StageTimings, upload: Option<Duration>, stats: ChunkStats)
+    {
+        let now = Instant::now();
+        self.results.push(FrameTimings {frame: now - self.frame_start, stages, upload, stats});
+        self.frame_start = now;
+    }
+
+    pub fn columns() -> [&'static str; 6]
+    {
+        ["frame", "generate", "create_chunk", "surface_map", "mesh", "upload"]
+    }
+
+    fn column(f: &FrameTimings, i: usize) -> Option<f64>
+    {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        match i {
+            0 => Some(ms(f.frame)),
+            1 => Some(ms(f.stages.generate)),
+            2 => Some(ms(f.stages.create_chunk)),
+            3 => Some(ms(f.stages.surface_map)),
+            4 => Some(ms(f.stages.mesh)),
+            _ => f.upload.map(ms),
+        }
+    }
+
+    pub fn summaries(&self) -> Vec<(&'static str, Option<Summary>)>
+    {
+        Self::columns().iter().enumerate().map(|(i, name)| {
+            let values: Vec<f64> = self.results.iter().filter_map(|f| Self::column(f, i)).collect();
+            (*name, if values.is_empty() {None} else {Some(Summary::from_millis(values))})
+        }).collect()
+    }
+
+    // one row per frame, milliseconds, empty upload when headless
+    pub fn to_csv(&self) -> String
+    {
+        let mut text = format!("{},chunks,surface_maps,meshes,queue_chunk,queue_sfp,queue_mesh,bytes\n", Self::columns().join(","));
+        for f in self.results.iter() {
+            let cols: Vec<String> = (0 .. Self::columns().len())
+                .map(|i| Self::column(f, i).map_or(String::new(), |v| format!("{:.4}", v)))
+                .collect();
+            text += &format!("{},{},{},{},{},{},{},{}\n", cols.join(","),
+                f.stages.chunks, f.stages.surface_maps, f.stages.meshes,
+                f.stats.queue_chunk, f.stats.queue_sfp, f.stats.queue_mesh, f.stats.bytes);
+        }
+        text
+    }
+
+    pub fn to_json(&self) -> String
+    {
+        let total = |f: fn(&StageTimings) -> u32| self.results.iter().map(|r| f(&r.stages) as u64).sum::<u64>();
+        let mut text = format!(
+            "{{\n  \"frames\": {},\n  \"speed\": {},\n  \"distance\": {:.3},\n  \"chunks\": {},\n  \"surface_maps\": {},\n  \"meshes\": {},\n  \"stages_ms\": {{",
+            self.results.len(), self.speed, self.distance,
+            total(|s| s.chunks), total(|s| s.surface_maps), total(|s| s.meshes),
+        );
+        let summaries = self.summaries();
+        for (i, (name, summary)) in summaries.iter().enumerate() {
+            text += &format!("\n    \"{}\": ", name);
+            text += &match summary {
+                None => "null".to_string(),
+                Some(s) => format!(
+                    "{{\"mean\": {:.4}, \"p50\": {:.4}, \"p95\": {:.4}, \"p99\": {:.4}, \"max\": {:.4}}}",
+                    s.mean, s.p50, s.p95, s.p99, s.max),
+            };
+            if i + 1 < summaries.len() { text += ","; }
+        }
+        text += "\n  }";
+        if let Some(last) = self.results.last() {
+            let w = &last.stats;
+            text += &format!(
+                ",\n  \"world\": {{\"chunks\": {}, \"surface_maps\": {}, \"meshes\": {}, \"sdf_nodes\": {}, \"surface_nodes\": {}, \"bytes\": {}, \"queue_chunk\": {}, \"queue_sfp\": {}, \"queue_mesh\": {}}}",
+                w.chunks, w.surface_maps, w.meshes, w.sdf_nodes, w.surface_nodes, w.bytes,
+                w.queue_chunk, w.queue_sfp, w.queue_mesh);
+        }
+        text += "\n}\n";
+        text
+    }
+
+    // writes <prefix>.csv and <prefix>.json
+    pub fn write(&self, prefix: &str) -> Result<(), String>
+    {
+        let csv = format!("{}.csv", prefix);
+        let json = format!("{}.json", prefix);
+        std::fs::write(&csv, self.to_csv()).map_err(|e| format!("{}: {}", csv, e))?;
+        std::fs::write(&json, self.to_json()).map_err(|e| format!("{}: {}", json, e))?;
+        Ok(())
+    }
+
+    pub fn report(&self) -> String
+    {
+        let mut text = format!("{} frames, ms          mean      p50      p95      p99      max", self.results.len());
+        for (name, summary) in self.summaries() {
+            text += &match summary {
+                None => format!("\n  {:<14} n/a", name),
+                Some(s) => format!("\n  {:<14} {:8.3} {:8.3} {:8.3} {:8.3} {:8.3}", name, s.mean, s.p50, s.p95, s.p99, s.max),
+            };
+        }
+        text
+    }
+
+}
+
+//}}}
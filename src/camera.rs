@@ -0,0 +1,302 @@
+use glam::*;
+use crate::{
+    math::{*,
+        direction::*,
+        transform::Transform,
+    },
+    player::Player,
+    world::World,
+    render::globals::CameraUniform,
+    input::{InputState, Action, Axis},
+};
+
+//{{{ Camera
+
+// Camera -- projection for a view, fov is vertical in degrees
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Projection
+{
+    Perspective,                  // infinite far, reversed z
+    Orthographic { height: f64 }, // view height in world units, reversed z
+}
+
+pub struct Camera
+{
+    pub projection: Projection,
+    pub fov: f64,
+    pub near: f64,
+    pub far: f64,        // orthographic only, perspective is infinite
+    pub aspect: f64,     // width / height
+    pub zoom: f64,       // fov divisor
+    pub zoom_target: f64,
+    pub zoom_speed: f64, // 1/s
+}
+
+impl Default for Camera
+{
+    fn default() -> Self
+    {
+        Self
+        {
+            projection: Projection::Perspective,
+            fov: 90.0,
+            near: 0.1,
+            far: 10000.0,
+            aspect: 800.0 / 600.0,
+            zoom: 1.0,
+            zoom_target: 1.0,
+            zoom_speed: 8.0,
+        }
+    }
+}
+
+impl Camera
+{
+
+    pub fn new(width: u32, height: u32) -> Self
+    {
+        let mut camera = Self {..Default::default()};
+        camera.resize(width, height);
+        camera
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32)
+    {
+        if width == 0 || height == 0 {return;}
+        self.aspect = width as f64 / height as f64;
+    }
+
+    // effective fov after zoom
+    pub fn get_fov(&self) -> f64 { self.fov / self.zoom }
+
+    pub fn zoom_to(&mut self, zoom: f64) { self.zoom_target = zoom.max(0.01); }
+
+    pub fn update(&mut self, elapsed_time: f64)
+    {
+        let t = (self.zoom_speed * elapsed_time).min(1.0);
+        self.zoom += (self.zoom_target - self.zoom) * t;
+    }
+
+    pub fn mat_proj(&self) -> DMat4
+    {
+        let ratio = 1.0 / self.aspect;
+        match self.projection {
+            Projection::Perspective => mat_projection(self.get_fov(), ratio, self.near, self.far),
+            Projection::Orthographic{height} => mat_orthographic(height / self.zoom, ratio, self.near, self.far),
+        }
+    }
+
+    pub fn to_uniform(&self, view: &Transform) -> CameraUniform
+    {
+        CameraUniform{
+            position: view.position.as_vec3().extend(1.0).to_array(),
+            mat_view: view.inverse_mat4().as_mat4().to_cols_array_2d(),
+            mat_proj: self.mat_proj().as_mat4().to_cols_array_2d(),
+        }
+    }
+
+}
+
+//}}}
+
+//{{{ CameraRig
+
+// Camera rigs -- where the view is placed relative to the player
+// output is a world space transform, blended when switching rigs
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RigMode
+{
+    Cockpit, // player camera transform
+    Chase,   // spring damped, behind and above
+    Orbit,   // around the player mesh
+    Free,    // detached, takes over the controls
+}
+
+impl RigMode
+{
+    pub fn next(&self) -> Self
+    {
+        match self {
+            RigMode::Cockpit => RigMode::Chase,
+            RigMode::Chase => RigMode::Orbit,
+            RigMode::Orbit => RigMode::Free,
+            RigMode::Free => RigMode::Cockpit,
+        }
+    }
+}
+
+pub struct CameraRig
+{
+    pub mode: RigMode,
+    pub current: Transform,
+    // chase
+    pub chase_offset: DVec3,     // player space
+    pub chase_lookahead: f64,    // look at a point this far ahead of the player
+    pub chase_stiffness: f64,
+    pub chase_damping: f64,
+    pub chase_position: DVec3,
+    pub chase_velocity: DVec3,
+    // orbit
+    pub orbit_distance: f64,
+    pub orbit_angles: DVec2,     // yaw, pitch
+    pub orbit_speed: f64,
+    // free
+    pub free: Transform,
+    pub free_speed: f64,
+    pub free_sensitivity: f64,
+    // terrain avoidance
+    pub clearance: f64,
+    // blending
+    pub blend_time: f64,
+    pub blend_elapsed: f64,
+    pub blend_from: Transform,
+}
+
+impl Default for CameraRig
+{
+    fn default() -> Self
+    {
+        Self
+        {
+            mode: RigMode::Cockpit,
+            current: Transform::IDENTITY,
+            chase_offset: dvec3(0.0, 2.0, -8.0),
+            chase_lookahead: 4.0,
+            chase_stiffness: 40.0,
+            chase_damping: 12.0,
+            chase_position: DDirection::ZERO,
+            chase_velocity: DDirection::ZERO,
+            orbit_distance: 10.0,
+            orbit_angles: dvec2(0.0, 0.3),
+            orbit_speed: 1.5,
+            free: Transform::IDENTITY,
+            free_speed: 15.0,
+            free_sensitivity: 0.005, // radians per count
+            clearance: 0.5,
+            blend_time: 0.5,
+            blend_elapsed: 0.5,
+            blend_from: Transform::IDENTITY,
+        }
+    }
+}
+
+impl CameraRig
+{
+
+    pub fn new() -> Self
+    {
+        Self {..Default::default()}
+    }
+
+    // free camera drives itself, the player gets no input
+    pub fn captures_input(&self) -> bool { self.mode == RigMode::Free }
+
+    pub fn set_mode(&mut self, mode: RigMode)
+    {
+        if mode == self.mode {return;}
+        self.blend_from = self.current;
+        self.blend_elapsed = 0.0;
+        match mode {
+            RigMode::Chase => {
+                self.chase_position = self.current.position;
+                self.chase_velocity = DDirection::ZERO;
+            }
+            RigMode::Free => {
+                self.free = self.current;
+            }
+            _ => {}
+        }
+        self.mode = mode;
+    }
+
+    pub fn update(&mut self, elapsed_time: f64, player: &Player, world: &dyn World, input: &InputState)
+    {
+        if input.pressed(Action::CameraSwitch) {
+            self.set_mode(self.mode.next());
+        }
+
+        let target = match self.mode {
+            RigMode::Cockpit => player.get_camera_transform(),
+            RigMode::Chase => self.update_chase(elapsed_time, player, world),
+            RigMode::Orbit => self.update_orbit(elapsed_time, player, world, input),
+            RigMode::Free => self.update_free(elapsed_time, input),
+        };
+
+        self.blend_elapsed = (self.blend_elapsed + elapsed_time).min(self.blend_time);
+        self.current = if self.blend_elapsed < self.blend_time {
+            let t = self.blend_elapsed / self.blend_time;
+            self.blend_from.lerp(&target, t * t * (3.0 - 2.0 * t))
+        } else {
+            target
+        };
+    }
+
+    // keep the camera outside the terrain along the line of sight to the anchor
+    pub fn avoid_terrain(&self, world: &dyn World, anchor: DVec3, desired: DVec3) -> DVec3
+    {
+        let delta = desired - anchor;
+        let dist = delta.length();
+        if dist <= 0.0 {return desired;}
+        let mut pos = match world.sphere_cast(anchor, delta, self.clearance, dist) {
+            None => desired,
+            Some(hit) => anchor + delta / dist * hit.distance,
+        };
+        if let Some((_, normal, depth)) = world.sphere_contact(pos, self.clearance) {
+            pos += normal * depth;
+        }
+        pos
+    }
+
+    pub fn update_chase(&mut self, elapsed_time: f64, player: &Player, world: &dyn World) -> Transform
+    {
+        let anchor = player.get_position();
+        let desired = player.transform.transform_point(self.chase_offset);
+        let accel = (desired - self.chase_position) * self.chase_stiffness
+            - self.chase_velocity * self.chase_damping;
+        self.chase_velocity += accel * elapsed_time;
+        self.chase_position += self.chase_velocity * elapsed_time;
+        self.chase_position = self.avoid_terrain(world, anchor, self.chase_position);
+
+        let look_at = anchor + player.transform.forward() * self.chase_lookahead;
+        let forward = look_at - self.chase_position;
+        if forward.length_squared() < 1e-9 {return player.get_camera_transform();}
+        Transform::new(self.chase_position, look_rotation(forward, player.transform.up()))
+    }
+
+    pub fn update_orbit(&mut self, elapsed_time: f64, player: &Player, world: &dyn World, input: &InputState) -> Transform
+    {
+        let step = self.orbit_speed * elapsed_time;
+        self.orbit_angles += dvec2(input.axis(Axis::CameraYaw), input.axis(Axis::CameraPitch)) * step;
+        let limit = std::f64::consts::FRAC_PI_2 - 0.05;
+        self.orbit_angles.y = self.orbit_angles.y.clamp(-limit, limit);
+
+        let anchor = player.get_position();
+        let rotation = quat_rotation(dvec3(self.orbit_angles.y, self.orbit_angles.x, 0.0));
+        let desired = anchor + rotation * (DDirection::BACK * self.orbit_distance);
+        let position = self.avoid_terrain(world, anchor, desired);
+        Transform::new(position, look_rotation(anchor - position, DDirection::UP))
+    }
+
+    pub fn update_free(&mut self, elapsed_time: f64, input: &InputState) -> Transform
+    {
+        let trans = dvec3(
+            input.axis(Axis::MoveX),
+            input.axis(Axis::MoveY),
+            input.axis(Axis::MoveZ),
+        ) * self.free_speed * elapsed_time;
+        let rot = dvec3(input.axis(Axis::LookY), input.axis(Axis::LookX), 0.0) * self.free_sensitivity;
+        self.free.translate_local(trans);
+        self.free.rotate_local(rot);
+        self.free
+    }
+
+    pub fn mat_view(&self) -> DMat4
+    {
+        self.current.inverse_mat4()
+    }
+
+}
+
+//}}}
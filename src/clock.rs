@@ -0,0 +1,93 @@
+// Fixed timestep clock -- frame time goes into an accumulator,
+// the simulation runs whole ticks out of it and rendering
+// interpolates by the leftover fraction
+
+pub struct FixedClock
+{
+    pub tick_rate: f64,      // Hz
+    pub max_steps: u32,      // catch-up limit per frame, the rest is dropped
+    pub time_scale: f64,     // debug slow motion / fast forward
+    pub paused: bool,
+    pub step_requested: bool, // run a single tick while paused
+    pub accumulator: f64,
+    pub tick: u64,           // ticks run so far
+    pub dropped: f64,        // seconds thrown away by the catch-up limit
+}
+
+impl Default for FixedClock
+{
+    fn default() -> Self
+    {
+        Self
+        {
+            tick_rate: 60.0,
+            max_steps: 8,
+            time_scale: 1.0,
+            paused: false,
+            step_requested: false,
+            accumulator: 0.0,
+            tick: 0,
+            dropped: 0.0,
+        }
+    }
+}
+
+impl FixedClock
+{
+
+    pub fn new(tick_rate: f64) -> Self
+    {
+        Self {tick_rate, ..Default::default()}
+    }
+
+    #[inline]
+    pub fn dt(&self) -> f64 { 1.0 / self.tick_rate }
+
+    // number of ticks to run for this frame
+    pub fn advance(&mut self, elapsed_time: f64) -> u32
+    {
+        if self.paused {
+            if self.step_requested {
+                self.step_requested = false;
+                self.tick += 1;
+                return 1;
+            }
+            return 0;
+        }
+        let dt = self.dt();
+        self.accumulator += elapsed_time.max(0.0) * self.time_scale;
+        let mut steps = (self.accumulator / dt).floor() as u32;
+        if steps > self.max_steps {
+            // hitch, keep the fraction so interpolation stays smooth
+            let keep = self.accumulator % dt;
+            self.dropped += self.accumulator - keep - self.max_steps as f64 * dt;
+            self.accumulator = keep + self.max_steps as f64 * dt;
+            steps = self.max_steps;
+        }
+        self.accumulator -= steps as f64 * dt;
+        self.tick += steps as u64;
+        steps
+    }
+
+    // 0..1 between the previous and the current tick
+    pub fn alpha(&self) -> f64
+    {
+        (self.accumulator / self.dt()).clamp(0.0, 1.0)
+    }
+
+    pub fn toggle_pause(&mut self)
+    {
+        self.paused = ! self.paused;
+    }
+
+    pub fn step(&mut self)
+    {
+        self.step_requested = true;
+    }
+
+    pub fn scale_time(&mut self, factor: f64)
+    {
+        self.time_scale = (self.time_scale * factor).clamp(1.0 / 64.0, 8.0);
+    }
+
+}
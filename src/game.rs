@@ -1,30 +1,38 @@
-use std::collections::HashSet;
 use glam::*;
-use sdl2::keyboard::Keycode;
 use crate::{
     math::{*,
         hasher::*,
         direction::*,
+        transform::Transform,
+    },
+    player::{
+        Player, PlayerMode,
+        flight::FlightModel,
+        collision::Collider,
     },
-    player::Player,
     world::{*,
         //bobbins::BobbinsWorld,
         sdftest::SdfWorld,
     },
     render::{*,
-        globals::CameraUniform,
+        globals::{CameraUniform, ObjectUniform},
     },
+    camera::{Camera, CameraRig, RigMode},
+    input::{InputState, Action},
 };
 
 pub struct Game {
     pub world: Box<dyn World>,
     pub light: Light,
     pub player : Player,
+    pub camera: Camera,
+    pub camera_rig: CameraRig,
     pub object_mesh: Mesh,
-    pub mat_proj : DMat4,
     pub mat_view : DMat4,
-    pub screen_width: i32,
-    pub screen_height: i32,
+    // render interpolation, state as of the previous tick
+    pub prev_transform: Transform,
+    pub prev_view: Transform,
+    pub alpha: f64,
 }
 
 impl Game {
@@ -33,7 +41,12 @@ impl Game {
 
     pub fn new() -> Self
     {
-        let mut default_mesh: Mesh = Mesh{..Default::default()};
+        Self::with_seed(0)
+    }
+
+    pub fn with_seed(seed: u32) -> Self
+    {
+        let mut default_mesh: Mesh = Mesh::placeholder_aircraft();
         // default_mesh.load_from_object_file("./models/planejane.obj".to_string());
         // default_mesh.load_texture("./models/planejaneUV.png".to_string());
         
@@ -43,7 +56,7 @@ impl Game {
         Self
         {
             //world: Box::new(BobbinsWorld::new()), // 2^n
-            world: Box::new(SdfWorld::new()),
+            world: Box::new(SdfWorld::with_seed(seed)),
             light: Light::new(
                 dvec3(1.0, 0.1, 0.1), 0.1, //ambient
                 dvec3(1.0, 1.0, 1.0), 0.2, //diffuse
@@ -51,15 +64,18 @@ impl Game {
             ),
             player : Player {
                 mesh: default_mesh,
-                position: DDirection::ZERO,
-                rotation: mat_rotation(dvec3(0.0, 0.0, 0.0)),
-                // camera_pos: dvec3(0.0, -5.0, 10.0),
-                camera_pos: dvec3(0.0, 0.0, 0.0),
-                camera_rot: mat_rotation(dvec3(0.0, 0.0, 0.0)),
+                transform: Transform::IDENTITY,
+                // camera: Transform::from_position(dvec3(0.0, -5.0, 10.0)),
+                camera: Transform::IDENTITY,
                 player_speed: 15.0,
                 camera_speed: 3.0, //keyboard only
-                mouse_sensitivity: 0.5,
+                mouse_sensitivity: 0.005, // radians per count
+                mode: PlayerMode::Spectator,
+                flight: FlightModel::new(),
+                collider: Collider::new(),
             },
+            camera: Camera::new(800, 600),
+            camera_rig: CameraRig::new(),
             object_mesh: Mesh{..Default::default()},
             mat_view : dmat4(
                 dvec4(0.0, 0.0, 0.0, 0.0),
@@ -67,14 +83,9 @@ impl Game {
                 dvec4(0.0, 0.0, 0.0, 0.0),
                 dvec4(0.0, 0.0, 0.0, 0.0),
             ),
-            mat_proj : dmat4(
-                dvec4(0.0, 0.0, 0.0, 0.0),
-                dvec4(0.0, 0.0, 0.0, 0.0),
-                dvec4(0.0, 0.0, 0.0, 0.0),
-                dvec4(0.0, 0.0, 0.0, 0.0),
-            ),
-            screen_width: 800,
-            screen_height: 400,
+            prev_transform: Transform::IDENTITY,
+            prev_view: Transform::IDENTITY,
+            alpha: 1.0,
         }
     }
 
@@ -82,14 +93,6 @@ impl Game {
 
     pub fn initialize(&mut self) -> Result<(), String>
     {
-        let near = 0.1;
-        let far = 10000.0;
-        let fov = 90.0;
-        // TODO gamestate resizing
-        // self.screen_height = olc::screen_height();
-        // self.screen_width = olc::screen_width();
-        let ratio = self.screen_height as f64 / self.screen_width as f64;
-        self.mat_proj = mat_projection(fov, ratio, near, far);
         self.world.initialize();
         self.object_mesh = Mesh{..Default::default()};
         // self.object_mesh.load_from_object_file("./models/planejane.obj".to_string());
@@ -98,20 +101,53 @@ impl Game {
         Ok(())
     }
 
-    pub fn update(&mut self, elapsed_time: f32, keys: &HashSet<Keycode>, mouse_pos: IVec2) -> Result<(), String>
+    // one fixed tick
+    pub fn update(&mut self, elapsed_time: f32, input: &InputState) -> Result<(), String>
     {
-        self.player.update(elapsed_time as f64, keys, mouse_pos);
+        self.prev_transform = self.player.transform;
+        self.prev_view = self.camera_rig.current;
+        let prev = self.player.transform.position;
+        if self.camera_rig.captures_input() {
+            // free camera has the controls
+            self.player.update(elapsed_time as f64, &InputState::default());
+        } else {
+            self.player.update(elapsed_time as f64, input);
+        }
+        self.player.collide(&*self.world, prev, elapsed_time as f64);
+        if let Some(speed) = self.player.collider.crashed() {
+            log::info!("crash {:.1}", speed);
+        }
         self.world.update(&self.player);
+        self.camera_rig.update(elapsed_time as f64, &self.player, &*self.world, input);
+        // hold Z for the targeting view
+        self.camera.zoom_to(if input.held(Action::Zoom) {3.0} else {1.0});
+        self.camera.update(elapsed_time as f64);
         // inverse look at
-        self.mat_view = self.player.mat_view();
+        self.mat_view = self.camera_rig.mat_view();
         Ok(())
     }
 
-    pub fn get_tris_to_raster(&self) -> Vec<Triangle> {
-        let mut tris_to_raster = Vec::with_capacity(1000);
-        self.object_mesh.preprocess_mesh(&mut tris_to_raster);
-        self.player.mesh.preprocess_mesh(&mut tris_to_raster);
-        tris_to_raster
+    // model space triangles and the draws that place them
+    pub fn get_objects(&self) -> (Vec<Triangle>, Vec<ObjectDraw>) {
+        let mut tris = Vec::with_capacity(1000);
+        let mut objects = vec![];
+        let mut add = |mesh: &Mesh, model: DMat4| {
+            let first = tris.len();
+            mesh.model_triangles(&mut tris);
+            if tris.len() > first {
+                objects.push(ObjectDraw {
+                    first: first as u32,
+                    count: (tris.len() - first) as u32,
+                    uniform: ObjectUniform::new(model * mesh.model_mat4()),
+                });
+            }
+        };
+        add(&self.object_mesh, DMat4::IDENTITY);
+        // the cockpit view sits inside the player
+        if self.camera_rig.mode != RigMode::Cockpit {
+            add(&self.player.mesh, self.get_render_transform().to_mat4());
+        }
+        (tris, objects)
     }
 
     pub fn destroy(&mut self) -> Result<(), String>
@@ -119,21 +155,37 @@ impl Game {
         Ok(())
     }
 
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.camera.resize(width, height);
+    }
+
+    // fraction of a tick since the last update, set before rendering
+    pub fn set_interpolation(&mut self, alpha: f64) {
+        self.alpha = alpha;
+    }
+
+    pub fn get_render_transform(&self) -> Transform {
+        self.prev_transform.lerp(&self.player.transform, self.alpha)
+    }
+
+    pub fn get_render_view(&self) -> Transform {
+        self.prev_view.lerp(&self.camera_rig.current, self.alpha)
+    }
+
     pub fn get_camera_uniform(&self) -> CameraUniform {
-        CameraUniform{
-            position: self.player.get_camera_pos().as_vec3().extend(1.0).to_array(),
-            mat_view: self.mat_view.as_mat4().to_cols_array_2d(),
-            mat_proj: self.mat_proj.as_mat4().to_cols_array_2d(),
-        }
+        self.camera.to_uniform(&self.get_render_view())
     }
 
     pub fn get_gamedata(&self) -> GameData {
         let meshes = self.world.get_meshes();
+        let (general_triangles, objects) = self.get_objects();
         GameData {
             //terrain: self.world.get_data(),
-            general_triangles: self.get_tris_to_raster(),
+            general_triangles,
+            objects,
             visible_meshes: meshes.0,
             updated_mesh_keys: meshes.1,
+            chunk_extent: self.world.chunk_extent() as f32,
             camera: self.get_camera_uniform(),
             light: self.light.to_light_uniform(),
         }
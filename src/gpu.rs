@@ -5,30 +5,93 @@
 #![allow(unused_mut)]
 #![allow(unused_must_use)]
 
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
 use wgpu::*;
 use sdl2::video::Window;
 use crate::{
-    render::{*, sdf::*, terrain::*},
+    render::{*, sdf::*, terrain::*, hud::*},
     game::Game
 };
 
+// failures Gpu recovers from, injected with force_error to exercise that
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GpuFault {
+    Timeout,
+    Outdated,
+    Lost,
+    DeviceLost,
+}
+
+impl GpuFault {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "timeout" => Some(Self::Timeout),
+            "outdated" => Some(Self::Outdated),
+            "lost" => Some(Self::Lost),
+            "device" => Some(Self::DeviceLost),
+            _ => None,
+        }
+    }
+}
+
+// after a forced device loss, the meshes resident before it and frames drawn since
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecoveryCheck {
+    pub before: usize,
+    pub frames: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecoveryStatus {
+    Recovered,
+    Pending,
+    Stalled, // meshes not back within RecoveryCheck::FRAMES
+}
+
+impl RecoveryCheck {
+    pub const FRAMES: usize = 120;
+
+    pub fn new(before: usize) -> Self {
+        Self {before, frames: 0}
+    }
+
+    // once per drawn frame with the meshes resident by then
+    pub fn step(&mut self, meshes: usize) -> RecoveryStatus {
+        if meshes >= self.before {return RecoveryStatus::Recovered;}
+        if self.frames >= Self::FRAMES {return RecoveryStatus::Stalled;}
+        self.frames += 1;
+        RecoveryStatus::Pending
+    }
+}
+
 pub struct Gpu {
+    pub instance: Instance,
     pub surface: Surface,
+    pub adapter: Adapter,
     pub device: Device,
     pub queue: Queue,
     pub config: SurfaceConfiguration,
+    pub terrain: TerrainConfig, // as requested, passes are built again from it
     pub pass: Box<dyn Pass>,
+    pub hud: HudPass, // drawn after pass
+    pub minimized: bool, // zero sized surface, nothing is drawn
+    pub device_lost: Arc<AtomicBool>, // set from the error handler
+    pub forced: Option<GpuFault>,     // test hook, taken by the next render
+    pub recovery: Option<RecoveryCheck>,
+    pub recover_failures: usize, // rebuilds in a row that failed
 }
 
 impl Gpu {
 
-    // TODO max buffer size is only absolute max. could downscale if needed
-    // expected 128 MB?
-    pub fn max_verts() -> u64 { Limits::downlevel_defaults().max_buffer_size / Vertex::size_of() as u64 }
-    pub fn max_inds() -> u64 { Limits::downlevel_defaults().max_buffer_size / 4 } // 4 bytes per u32
+    // a loss that outlasts this many rebuilds is treated as permanent
+    pub const RECOVER_ATTEMPTS: usize = 10;
+
+    // absolute max for one buffer, the terrain pools size themselves from a budget
+    pub fn max_verts(&self) -> u64 { self.device.limits().max_buffer_size / Vertex::size_of() as u64 }
+    pub fn max_inds(&self) -> u64 { self.device.limits().max_buffer_size / 4 } // 4 bytes per u32
 
     // Creating some of the wgpu types requires async code
-    pub async fn new(window: &Window) -> Gpu {
+    pub async fn new(window: &Window, terrain: TerrainConfig) -> Gpu {
         let (width, height) = window.size();
 
         let instance = Instance::new( InstanceDescriptor {
@@ -38,90 +101,329 @@ impl Gpu {
         });
 
         let surface = unsafe { instance.create_surface(window) }.unwrap();
+        let adapter = Self::request_adapter(&instance, &surface).await.unwrap();
+        let (device, queue) = Self::request_device(&adapter).await.unwrap();
+        let device_lost = Self::watch(&device);
 
-        let adapter = instance.request_adapter(
+        let config = Self::surface_config(&surface, &adapter, width, height);
+        surface.configure(&device, &config);
+        let (pass, hud) = Self::create_passes(&adapter, &device, &queue, &config, terrain);
+
+        Self {
+            instance,
+            surface,
+            adapter,
+            device,
+            queue,
+            config,
+            terrain,
+            pass,
+            hud,
+            minimized: width == 0 || height == 0,
+            device_lost,
+            forced: None,
+            recovery: None,
+            recover_failures: 0,
+        }
+    }
+
+    async fn request_adapter(instance: &Instance, surface: &Surface) -> Option<Adapter> {
+        instance.request_adapter(
             &RequestAdapterOptions {
                 power_preference: PowerPreference::HighPerformance,
                 force_fallback_adapter: false,
-                compatible_surface: Some(&surface),
+                compatible_surface: Some(surface),
             }
-        ).await.unwrap();
+        ).await
+    }
 
-        let (device, queue) = adapter.request_device(
+    async fn request_device(adapter: &Adapter) -> Result<(Device, Queue), RequestDeviceError> {
+        adapter.request_device(
             &DeviceDescriptor {
-                limits: Limits::downlevel_defaults(),
-                features: Features::VERTEX_WRITABLE_STORAGE,
+                // everything the adapter has, passes size their buffers from device.limits()
+                limits: adapter.limits(),
+                // multi draw is optional, terrain falls back to a draw per mesh
+                features: Features::VERTEX_WRITABLE_STORAGE
+                    | (adapter.features() & (Features::MULTI_DRAW_INDIRECT | Features::MULTI_DRAW_INDIRECT_COUNT)),
                 label: Some("device"),
             },
             None,
-        ).await.unwrap();
+        ).await
+    }
+
+    // losing the device flags it for recover, other errors are logged and the frame goes on
+    fn watch(device: &Device) -> Arc<AtomicBool> {
+        let lost = Arc::new(AtomicBool::new(false));
+        let flag = lost.clone();
+        device.on_uncaptured_error(Box::new(move |err| {
+            if Self::lost_device(&err) {
+                log::error!("device lost: {}", err);
+                flag.store(true, Ordering::Relaxed);
+            } else {
+                log::error!("wgpu error: {}", err);
+            }
+        }));
+        lost
+    }
 
-        let surface_caps = surface.get_capabilities(&adapter);
+    // DeviceError::Lost anywhere in the error's sources
+    // wgpu_core wraps it transparently, which hides it from source(), so the wrappers are matched too
+    fn lost_device(err: &Error) -> bool {
+        use wgpu_core::{
+            device::{DeviceError, queue::{QueueWriteError, QueueSubmitError}},
+            resource::{BufferAccessError, CreateBufferError, CreateTextureError, CreateSamplerError},
+            binding_model::{CreateBindGroupError, CreateBindGroupLayoutError, CreatePipelineLayoutError},
+            pipeline::{CreateShaderModuleError, CreateRenderPipelineError, CreateComputePipelineError},
+        };
+        macro_rules! lost {
+            ($e:expr, $($wrapper:ident :: $variant:ident),*) => {
+                matches!($e.downcast_ref::<DeviceError>(), Some(DeviceError::Lost))
+                    $(|| matches!($e.downcast_ref::<$wrapper>(), Some($wrapper::$variant(DeviceError::Lost))))*
+            };
+        }
+        let mut next = std::error::Error::source(err);
+        while let Some(e) = next {
+            if lost!(e,
+                QueueWriteError::Queue, QueueSubmitError::Queue,
+                BufferAccessError::Device, CreateBufferError::Device, CreateTextureError::Device, CreateSamplerError::Device,
+                CreateBindGroupError::Device, CreateBindGroupLayoutError::Device, CreatePipelineLayoutError::Device,
+                CreateShaderModuleError::Device, CreateRenderPipelineError::Device, CreateComputePipelineError::Device)
+            {
+                return true;
+            }
+            next = e.source();
+        }
+        false
+    }
+
+    fn surface_config(surface: &Surface, adapter: &Adapter, width: u32, height: u32) -> SurfaceConfiguration {
+        let surface_caps = surface.get_capabilities(adapter);
         let surface_format = surface_caps.formats.iter()
             .copied()
             .filter(|f| f.describe().srgb)
             .next()
             .unwrap_or(surface_caps.formats[0]);
-        let config = SurfaceConfiguration {
+        SurfaceConfiguration {
             usage: TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
-            width,
-            height,
+            width: width.max(1),
+            height: height.max(1),
             // present_mode: PresentMode::AutoVsync,
             present_mode: PresentMode::AutoNoVsync,
             alpha_mode: CompositeAlphaMode::Auto,
             view_formats: vec![],
+        }
+    }
+
+    fn create_passes(
+        adapter: &Adapter,
+        device: &Device,
+        queue: &Queue,
+        config: &SurfaceConfiguration,
+        terrain: TerrainConfig,
+    ) -> (Box<dyn Pass>, HudPass) {
+        // gpu culling needs compute, downlevel adapters keep the cpu frustum test
+        // naga can't write depth texture loads as glsl, so neither does gl
+        let terrain = TerrainConfig {
+            gpu_culling: terrain.gpu_culling
+                && adapter.get_downlevel_capabilities().flags.contains(DownlevelFlags::COMPUTE_SHADERS)
+                && adapter.get_info().backend != Backend::Gl,
+            ..terrain
         };
-        surface.configure(&device, &config);
+        let pass = Box::new( TerrainPass::new(&terrain, device, config) );
+        //let pass = Box::new( SdfPass::new(device, config) );
+        let hud = HudPass::new(device, queue, config);
+        (pass, hud)
+    }
 
-        let pass = Box::new( TerrainPass::new(&TerrainConfig {}, &device, &config) );
-        //let pass = Box::new( SdfPass::new(&device, &config) );
+    pub fn upload_time(&self) -> std::time::Duration { self.pass.upload_time() }
 
-        Self {
-            surface,
-            device,
-            queue,
-            config,
-            pass,
-        }
-    }
+    pub fn buffer_stats(&self) -> Option<BufferStats> { self.pass.buffer_stats() }
 
     pub fn width(&self) -> u32 { self.config.width }
     pub fn height(&self) -> u32 { self.config.height }
 
+    // a zero sized surface can't be configured, wait for a real size
     pub fn resize(&mut self, width: u32, height: u32) {
+        self.minimized = width == 0 || height == 0;
+        if self.minimized {return;}
+        if width == self.config.width && height == self.config.height {return;}
         self.config.width = width as u32;
         self.config.height = height as u32;
         self.surface.configure(&self.device, &self.config);
+        self.pass.resize(&self.device, &self.config);
+        self.hud.resize(width, height);
     }
 
-    pub fn get_current_texture(&self) -> SurfaceTexture {
-        return match self.surface.get_current_texture() {
-            Ok(frame) => frame,
-            Err(err) => {
-                let reason = match err {
-                    SurfaceError::Timeout => "Timeout",
-                    SurfaceError::Outdated => "Outdated",
-                    SurfaceError::Lost => "Lost",
-                    SurfaceError::OutOfMemory => "OutOfMemory",
-                };
-                panic!("Failed to get current surface texture! Reason: {}", reason)
+    // the next render fails as if fault happened
+    // a lost device is then checked to get back the meshes it had, see check_recovery
+    pub fn force_error(&mut self, fault: GpuFault) {
+        self.forced = Some(fault);
+        if fault == GpuFault::DeviceLost {
+            self.recovery = Some(RecoveryCheck::new(self.buffer_stats().map_or(0, |s| s.meshes)));
+        }
+    }
+
+    pub fn is_device_lost(&self) -> bool { self.device_lost.load(Ordering::Relaxed) }
+
+    pub fn is_recovering(&self) -> bool { self.recovery.is_some() }
+
+    // once per drawn frame, an error when the meshes aren't back within RecoveryCheck::FRAMES
+    pub fn check_recovery(&mut self) -> Result<(), String> {
+        if self.minimized {return Ok(());}
+        let meshes = self.buffer_stats().map_or(0, |s| s.meshes);
+        let check = match &mut self.recovery {
+            Some(check) => check,
+            None => return Ok(()),
+        };
+        match check.step(meshes) {
+            RecoveryStatus::Recovered => {
+                log::info!("device recovered, {} meshes back after {} frames", meshes, check.frames);
+                self.recovery = None;
+            }
+            RecoveryStatus::Pending => {}
+            RecoveryStatus::Stalled => {
+                return Err(format!("device recovery stalled, {} of {} meshes after {} frames",
+                    meshes, check.before, check.frames));
             }
+        }
+        Ok(())
+    }
+
+    // new adapter, device and passes on the same surface
+    // the terrain pass starts empty, the world's visible meshes are uploaded again
+    // over the next frames like on startup
+    pub fn recover(&mut self) -> Result<(), String> {
+        log::warn!("device lost, recreating");
+        let adapter = pollster::block_on(Self::request_adapter(&self.instance, &self.surface))
+            .ok_or("no adapter")?;
+        let (device, queue) = pollster::block_on(Self::request_device(&adapter))
+            .map_err(|e| e.to_string())?;
+        let config = Self::surface_config(&self.surface, &adapter, self.config.width, self.config.height);
+        self.surface.configure(&device, &config);
+        let (pass, hud) = Self::create_passes(&adapter, &device, &queue, &config, self.terrain);
+        self.device_lost = Self::watch(&device);
+        self.pass = pass;
+        self.hud = hud;
+        self.config = config;
+        self.queue = queue;
+        self.device = device;
+        self.adapter = adapter;
+        Ok(())
+    }
+
+    // outdated and lost surfaces are configured again and tried once more
+    // a surface still lost after that went with the device
+    pub fn get_current_texture(&mut self) -> Result<SurfaceTexture, SurfaceError> {
+        let mut result = match self.forced.take() {
+            Some(GpuFault::Timeout) => Err(SurfaceError::Timeout),
+            Some(GpuFault::Outdated) => Err(SurfaceError::Outdated),
+            Some(GpuFault::Lost) => Err(SurfaceError::Lost),
+            Some(GpuFault::DeviceLost) => {
+                self.device_lost.store(true, Ordering::Relaxed);
+                Err(SurfaceError::Lost)
+            }
+            None => self.surface.get_current_texture(),
         };
+        if let Err(err @ (SurfaceError::Outdated | SurfaceError::Lost)) = &result {
+            log::warn!("surface {:?}, reconfiguring", err);
+            self.surface.configure(&self.device, &self.config);
+            result = self.surface.get_current_texture();
+            if let Err(SurfaceError::Lost) = result {
+                self.device_lost.store(true, Ordering::Relaxed);
+            }
+        }
+        result
     }
 
-    pub fn render(&mut self, gamedata: &GameData) -> Result<(), SurfaceError> {
-        let frame = self.get_current_texture();
+    // a frame that can't be drawn is skipped
+    // out of memory and a device that can't be recreated are returned
+    pub fn render(&mut self, gamedata: &GameData, hud: &Hud) -> Result<(), String> {
+        if self.minimized {return Ok(());}
+        if self.is_device_lost() {
+            match self.recover() {
+                Ok(()) => self.recover_failures = 0,
+                Err(err) => {
+                    self.recover_failures += 1;
+                    log::error!("device recovery failed ({}/{}): {}", self.recover_failures, Self::RECOVER_ATTEMPTS, err);
+                    if self.recover_failures >= Self::RECOVER_ATTEMPTS {
+                        return Err(format!("device lost, {} rebuilds failed: {}", self.recover_failures, err));
+                    }
+                }
+            }
+            return Ok(());
+        }
+        let frame = match self.get_current_texture() {
+            Ok(frame) => frame,
+            // busy presenting or still lost, try again next frame
+            Err(SurfaceError::Timeout | SurfaceError::Outdated | SurfaceError::Lost) => return Ok(()),
+            Err(err) => return Err(format!("{:?}", err)),
+        };
         let view = frame.texture.create_view(&TextureViewDescriptor::default());
         let mut encoder = self.device.create_command_encoder(&CommandEncoderDescriptor {
             label: Some("command_encoder"),
         });
-        self.pass.update(&self.queue, gamedata);
-        self.pass.draw(&view, &mut encoder);
+        self.pass.update(&self.device, &self.queue, &mut encoder, gamedata);
+        self.hud.update_hud(&self.queue, hud);
+        self.pass.draw(&view, &mut encoder).map_err(|e| format!("{:?}", e))?;
+        self.hud.draw(&view, &mut encoder).map_err(|e| format!("{:?}", e))?;
+        // submitting to a lost device is fatal, the frame is dropped instead
+        if self.is_device_lost() {
+            return Ok(());
+        }
         self.queue.submit([encoder.finish()]);
+        self.pass.recall();
+        self.device.poll(Maintain::Poll);
         frame.present();
         Ok(())
     }
 
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_faults() {
+        assert_eq!(GpuFault::parse("timeout"), Some(GpuFault::Timeout));
+        assert_eq!(GpuFault::parse("outdated"), Some(GpuFault::Outdated));
+        assert_eq!(GpuFault::parse("lost"), Some(GpuFault::Lost));
+        assert_eq!(GpuFault::parse("device"), Some(GpuFault::DeviceLost));
+        assert_eq!(GpuFault::parse("Device"), None);
+        assert_eq!(GpuFault::parse(""), None);
+    }
+
+    #[test]
+    fn recovery_check() {
+        let mut check = RecoveryCheck::new(100);
+        assert_eq!(check.step(0), RecoveryStatus::Pending);
+        assert_eq!(check.step(60), RecoveryStatus::Pending);
+        assert_eq!(check.frames, 2);
+        assert_eq!(check.step(100), RecoveryStatus::Recovered);
+        assert_eq!(RecoveryCheck::new(100).step(120), RecoveryStatus::Recovered);
+
+        let mut check = RecoveryCheck::new(100);
+        for _ in 0 .. RecoveryCheck::FRAMES {
+            assert_eq!(check.step(99), RecoveryStatus::Pending);
+        }
+        assert_eq!(check.step(99), RecoveryStatus::Stalled);
+        assert_eq!(check.step(100), RecoveryStatus::Recovered);
+    }
+
+    // errors as the wgpu backend reports them, the cause wrapped in a ContextError
+    fn uncaptured(cause: Box<dyn std::error::Error + Send + Sync>) -> Error {
+        let source = wgpu_core::error::ContextError {string: "test", cause, label_key: "label", label: String::new()};
+        Error::Validation {source: Box::new(source), description: String::new()}
+    }
+
+    #[test]
+    fn lost_device_errors() {
+        use wgpu_core::{device::{DeviceError, queue::QueueSubmitError}, resource::CreateBufferError};
+        assert!(Gpu::lost_device(&uncaptured(Box::new(DeviceError::Lost))));
+        assert!(Gpu::lost_device(&uncaptured(Box::new(QueueSubmitError::Queue(DeviceError::Lost)))));
+        assert!(Gpu::lost_device(&uncaptured(Box::new(CreateBufferError::Device(DeviceError::Lost)))));
+        assert!(! Gpu::lost_device(&uncaptured(Box::new(DeviceError::OutOfMemory))));
+        assert!(! Gpu::lost_device(&uncaptured(Box::new(CreateBufferError::UnalignedSize))));
+    }
+}
@@ -0,0 +1,222 @@
+use std::time::{Duration, Instant};
+use crate::{
+    game::Game,
+    input::{InputState, Action, Axis},
+    replay::Recording,
+    bench::Benchmark,
+    world::chunk::ChunkStats,
+};
+
+// Headless runner -- drives Game and World on fixed ticks, no window or gpu
+// input comes from a script or a recording
+//
+// script, one block per line, # starts a comment
+//   <ticks> [axis=value ...] [action ...] [+action ...]
+// axes hold their value for the block, actions are held for the block,
+// +action is also pressed on the first tick of the block
+//   60  move_z=1
+//   1   +toggle_mode
+//   600 throttle=1 pitch=0.1
+
+pub fn parse_script(text: &str) -> Result<Vec<InputState>, String>
+{
+    let mut ticks = vec![];
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {continue;}
+        let err = |e: String| format!("line {}: {}", n + 1, e);
+        let mut tokens = line.split_whitespace();
+        let count: usize = tokens.next().unwrap().parse().map_err(|_| err("bad tick count".to_string()))?;
+        let mut state = InputState::default();
+        let mut pressed = 0;
+        for token in tokens {
+            if let Some((name, value)) = token.split_once('=') {
+                let axis = Axis::from_name(name).ok_or_else(|| err(format!("unknown axis {}", name)))?;
+                state.axes[axis as usize] = value.parse().map_err(|_| err(format!("bad value {}", token)))?;
+            }
+            else {
+                let (name, press) = match token.strip_prefix('+') {
+                    Some(name) => (name, true),
+                    None => (token, false),
+                };
+                let action = Action::from_name(name).ok_or_else(|| err(format!("unknown action {}", name)))?;
+                state.held |= action.bit();
+                if press { pressed |= action.bit(); }
+            }
+        }
+        for i in 0 .. count {
+            let mut tick = state;
+            if i == 0 { tick.pressed = pressed; }
+            ticks.push(tick);
+        }
+    }
+    Ok(ticks)
+}
+
+// straight and level spectator flight, enough to stream in new chunks
+pub const DEFAULT_SCRIPT: &str = "\
+600 move_z=1
+";
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HeadlessStats
+{
+    pub ticks: u64,
+    pub sim_time: Duration,     // wall time spent in Game::update
+    pub slowest_tick: Duration,
+    pub chunks_start: usize,
+    pub chunks_end: usize,
+    pub process_memory: Option<usize>, // resident set, linux only
+    pub world: ChunkStats,
+}
+
+impl HeadlessStats
+{
+    pub fn chunks_generated(&self) -> usize { self.chunks_end.saturating_sub(self.chunks_start) }
+
+    pub fn report(&self) -> String
+    {
+        let secs = self.sim_time.as_secs_f64().max(1e-9);
+        let mut text = format!(
+            "ticks {}, {:.3}s, {:.1} ticks/s, slowest tick {:.2}ms\n\
+             chunks {} generated, {} total, {:.1} chunks/s\n\
+             {}",
+            self.ticks, secs, self.ticks as f64 / secs, self.slowest_tick.as_secs_f64() * 1000.0,
+            self.chunks_generated(), self.chunks_end, self.chunks_generated() as f64 / secs,
+            self.world,
+        );
+        if let Some(rss) = self.process_memory {
+            text += &format!("\nprocess {:.2} MiB", rss as f64 / (1024.0 * 1024.0));
+        }
+        text
+    }
+}
+
+// resident set size from /proc, None elsewhere
+pub fn process_memory() -> Option<usize>
+{
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: usize = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(pages * 4096)
+}
+
+pub struct HeadlessRunner
+{
+    pub game: Game,
+    pub tick_rate: f64,
+    pub stats: HeadlessStats,
+}
+
+impl HeadlessRunner
+{
+
+    pub fn new(seed: u32, tick_rate: f64) -> Result<Self, String>
+    {
+        let mut game = Game::with_seed(seed);
+        game.initialize()?;
+        let chunks_start = game.world.chunk_keys().len();
+        Ok(Self {
+            game,
+            tick_rate,
+            stats: HeadlessStats {chunks_start, ..Default::default()},
+        })
+    }
+
+    pub fn run(&mut self, ticks: &[InputState]) -> Result<(), String>
+    {
+        let dt = (1.0 / self.tick_rate) as f32;
+        for tick in ticks.iter() {
+            let start = Instant::now();
+            self.game.update(dt, tick)?;
+            // every tick is a frame
+            self.game.world.end_frame();
+            let elapsed = start.elapsed();
+            self.stats.sim_time += elapsed;
+            self.stats.slowest_tick = self.stats.slowest_tick.max(elapsed);
+            self.stats.ticks += 1;
+        }
+        self.stats.chunks_end = self.game.world.chunk_keys().len();
+        self.stats.world = self.game.world.chunk_stats();
+        self.stats.process_memory = process_memory();
+        Ok(())
+    }
+
+    pub fn run_benchmark(&mut self, bench: &mut Benchmark) -> Result<(), String>
+    {
+        bench.prepare(&mut self.game);
+        while ! bench.done() {
+            bench.step(&mut self.game)?;
+            let stages = self.game.world.end_frame();
+            bench.record(stages, None, self.game.world.chunk_stats());
+        }
+        Ok(())
+    }
+
+}
+
+// options shared with the windowed benchmark
+// --bench [--bench-out <prefix>] [--bench-frames <n>] [--bench-speed <units/s>] [--seed <n>]
+pub fn bench_from_args(args: &[String]) -> Result<Option<(Benchmark, String)>, String>
+{
+    if ! args.iter().any(|a| a == "--bench") {return Ok(None);}
+    let arg = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1)).cloned();
+    let parse = |name: &str, default: f64| -> Result<f64, String> {
+        arg(name).map_or(Ok(default), |v| v.parse().map_err(|_| format!("bad {} {}", name, v)))
+    };
+    let bench = Benchmark::new(
+        parse("--seed", 0.0)? as u32,
+        parse("--bench-speed", 20.0)?,
+        parse("--bench-frames", 1800.0)? as usize,
+    );
+    Ok(Some((bench, arg("--bench-out").unwrap_or("bench".to_string()))))
+}
+
+// --headless [--script <file>] [--seed <n>] [--tick-rate <hz>]
+// --headless --joystick-script <file> [--bindings <file>]  window builds, see VirtualJoystick::run_script
+// --replay <file>  replay a recording and check the outcome
+pub fn start(args: &[String]) -> Result<(), String>
+{
+    env_logger::try_init();
+    let arg = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1)).cloned();
+    let parse = |name: &str, default: f64| -> Result<f64, String> {
+        arg(name).map_or(Ok(default), |v| v.parse().map_err(|_| format!("bad {} {}", name, v)))
+    };
+
+    if let Some(path) = arg("--replay") {
+        let recording = Recording::load(&path)?;
+        let mut runner = HeadlessRunner::new(recording.seed, recording.tick_rate)?;
+        runner.run(&recording.ticks)?;
+        println!("{}", runner.stats.report());
+        let outcome = crate::replay::ReplayOutcome::from_game(&runner.game);
+        recording.outcome.compare(&outcome)?;
+        println!("replay ok, position {:?}, {} chunks", outcome.position, outcome.chunks.len());
+        return Ok(());
+    }
+
+    if let Some((mut bench, out)) = bench_from_args(args)? {
+        let mut runner = HeadlessRunner::new(parse("--seed", 0.0)? as u32, 60.0)?;
+        runner.run_benchmark(&mut bench)?;
+        println!("{}", bench.report());
+        bench.write(&out)?;
+        return Ok(());
+    }
+
+    let read = |path: String| std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e));
+    let tick_rate = parse("--tick-rate", 60.0)?;
+    let ticks = match (arg("--joystick-script"), arg("--script")) {
+        #[cfg(feature = "window")]
+        (Some(path), _) => {
+            let bindings = crate::input::InputManager::with_bindings_file(&arg("--bindings").unwrap_or("./bindings.cfg".to_string()))?.bindings;
+            crate::input::VirtualJoystick::run_script(&read(path)?, bindings, tick_rate)?
+        }
+        #[cfg(not(feature = "window"))]
+        (Some(_), _) => {return Err("--joystick-script needs a window build".to_string());}
+        (None, None) => parse_script(DEFAULT_SCRIPT)?,
+        (None, Some(path)) => parse_script(&read(path)?)?,
+    };
+    let mut runner = HeadlessRunner::new(parse("--seed", 0.0)? as u32, tick_rate)?;
+    runner.run(&ticks)?;
+    println!("{}", runner.stats.report());
+    println!("position {:?}", runner.game.player.transform.position);
+    Ok(())
+}
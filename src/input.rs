@@ -0,0 +1,151 @@
+// Input -- actions and axes decoupled from devices
+// devices feed raw values, bindings map them onto named actions and axes,
+// the game only ever sees an InputState
+// device handling and bindings live in sdl (window builds only)
+
+#[cfg(feature = "window")]
+pub mod sdl;
+#[cfg(feature = "window")]
+pub use sdl::*;
+
+//{{{ Action, Axis
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Action
+{
+    Fire,
+    CameraSwitch,
+    ToggleMode,
+    Zoom,
+    // debug, handled by the app clock
+    Pause,
+    Step,
+    SlowDown,
+    SpeedUp,
+    ToggleHud,
+}
+
+impl Action
+{
+    pub const ALL: [Action; 9] = [
+        Action::Fire,
+        Action::CameraSwitch,
+        Action::ToggleMode,
+        Action::Zoom,
+        Action::Pause,
+        Action::Step,
+        Action::SlowDown,
+        Action::SpeedUp,
+        Action::ToggleHud,
+    ];
+
+    pub fn name(&self) -> &'static str
+    {
+        match self {
+            Action::Fire => "fire",
+            Action::CameraSwitch => "camera_switch",
+            Action::ToggleMode => "toggle_mode",
+            Action::Zoom => "zoom",
+            Action::Pause => "pause",
+            Action::Step => "step",
+            Action::SlowDown => "slow_down",
+            Action::SpeedUp => "speed_up",
+            Action::ToggleHud => "toggle_hud",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self>
+    {
+        Self::ALL.iter().copied().find(|a| a.name() == name)
+    }
+
+    #[inline]
+    pub fn bit(&self) -> u32 { 1 << *self as u32 }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Axis
+{
+    // flight stick, -1..1
+    Pitch,       // positive nose up
+    Roll,        // positive roll right
+    Yaw,         // positive yaw right
+    Throttle,    // positive increases throttle
+    // spectator and free camera, -1..1
+    MoveX,       // right
+    MoveY,       // up
+    MoveZ,       // forward
+    LookRoll,    // roll right
+    // unbounded, mouse counts per tick (sticks are integrated over time)
+    LookX,       // yaw right
+    LookY,       // pitch down
+    // orbit camera, -1..1
+    CameraYaw,
+    CameraPitch,
+}
+
+pub const AXIS_COUNT: usize = 12;
+
+impl Axis
+{
+    pub const ALL: [Axis; AXIS_COUNT] = [
+        Axis::Pitch, Axis::Roll, Axis::Yaw, Axis::Throttle,
+        Axis::MoveX, Axis::MoveY, Axis::MoveZ, Axis::LookRoll,
+        Axis::LookX, Axis::LookY,
+        Axis::CameraYaw, Axis::CameraPitch,
+    ];
+
+    pub fn name(&self) -> &'static str
+    {
+        match self {
+            Axis::Pitch => "pitch",
+            Axis::Roll => "roll",
+            Axis::Yaw => "yaw",
+            Axis::Throttle => "throttle",
+            Axis::MoveX => "move_x",
+            Axis::MoveY => "move_y",
+            Axis::MoveZ => "move_z",
+            Axis::LookRoll => "look_roll",
+            Axis::LookX => "look_x",
+            Axis::LookY => "look_y",
+            Axis::CameraYaw => "camera_yaw",
+            Axis::CameraPitch => "camera_pitch",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self>
+    {
+        Self::ALL.iter().copied().find(|a| a.name() == name)
+    }
+
+    // look axes accumulate mouse counts and are not clamped
+    pub fn bounded(&self) -> bool
+    {
+        ! matches!(self, Axis::LookX | Axis::LookY)
+    }
+}
+
+//}}}
+
+//{{{ InputState
+
+// Everything the game reads for one update
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct InputState
+{
+    pub axes: [f64; AXIS_COUNT],
+    pub held: u32,    // action bits
+    pub pressed: u32, // action bits, held this update but not the last
+}
+
+impl InputState
+{
+    #[inline]
+    pub fn axis(&self, axis: Axis) -> f64 { self.axes[axis as usize] }
+    #[inline]
+    pub fn held(&self, action: Action) -> bool { self.held & action.bit() != 0 }
+    #[inline]
+    pub fn pressed(&self, action: Action) -> bool { self.pressed & action.bit() != 0 }
+}
+
+//}}}
@@ -0,0 +1,823 @@
+use std::collections::{HashSet, HashMap};
+use glam::*;
+use sdl2::{
+    Sdl, JoystickSubsystem, GameControllerSubsystem,
+    event::Event,
+    keyboard::Keycode,
+    mouse::MouseButton,
+    controller::{self, GameController},
+    joystick::Joystick,
+};
+use super::*;
+
+// SDL devices -- keyboard, mouse, game controllers and joysticks mapped through bindings
+
+//{{{ Bindings
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum InputSource
+{
+    Key(Keycode),
+    MouseButton(MouseButton),
+    MouseAxis(u8),                    // 0 x, 1 y, relative motion
+    ControllerAxis(controller::Axis),
+    ControllerButton(controller::Button),
+    JoystickAxis(u8),
+    JoystickButton(u8),
+}
+
+impl InputSource
+{
+    pub fn is_analog(&self) -> bool
+    {
+        matches!(self, InputSource::ControllerAxis(_) | InputSource::JoystickAxis(_))
+    }
+
+    // motion since the last event pump rather than a position
+    pub fn is_relative(&self) -> bool
+    {
+        matches!(self, InputSource::MouseAxis(_))
+    }
+
+    pub fn from_string(s: &str) -> Result<Self, String>
+    {
+        let (kind, name) = s.split_once(':').ok_or(format!("bad source {}", s))?;
+        let bad = || format!("unknown {} {}", kind, name);
+        match kind {
+            "key" => Keycode::from_name(name)
+                .or_else(|| Keycode::from_name(&name.replace('_', " ")))
+                .map(InputSource::Key).ok_or_else(bad),
+            "mouse" => match name {
+                "x" => Ok(InputSource::MouseAxis(0)),
+                "y" => Ok(InputSource::MouseAxis(1)),
+                "left" => Ok(InputSource::MouseButton(MouseButton::Left)),
+                "middle" => Ok(InputSource::MouseButton(MouseButton::Middle)),
+                "right" => Ok(InputSource::MouseButton(MouseButton::Right)),
+                "x1" => Ok(InputSource::MouseButton(MouseButton::X1)),
+                "x2" => Ok(InputSource::MouseButton(MouseButton::X2)),
+                _ => Err(bad()),
+            },
+            "controller_axis" => controller::Axis::from_string(name)
+                .map(InputSource::ControllerAxis).ok_or_else(bad),
+            "controller_button" => controller::Button::from_string(name)
+                .map(InputSource::ControllerButton).ok_or_else(bad),
+            "joystick_axis" => name.parse().map(InputSource::JoystickAxis).map_err(|_| bad()),
+            "joystick_button" => name.parse().map(InputSource::JoystickButton).map_err(|_| bad()),
+            _ => Err(bad()),
+        }
+    }
+}
+
+// names are written without spaces so a binding line splits on whitespace
+impl std::fmt::Display for InputSource
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+    {
+        match self {
+            InputSource::Key(k) => write!(f, "key:{}", k.name().replace(' ', "_")),
+            InputSource::MouseButton(b) => write!(f, "mouse:{}", match b {
+                MouseButton::Left => "left",
+                MouseButton::Middle => "middle",
+                MouseButton::Right => "right",
+                MouseButton::X1 => "x1",
+                MouseButton::X2 => "x2",
+                MouseButton::Unknown => "unknown",
+            }),
+            InputSource::MouseAxis(a) => write!(f, "mouse:{}", if *a == 0 {"x"} else {"y"}),
+            InputSource::ControllerAxis(a) => write!(f, "controller_axis:{}", a.string()),
+            InputSource::ControllerButton(b) => write!(f, "controller_button:{}", b.string()),
+            InputSource::JoystickAxis(a) => write!(f, "joystick_axis:{}", a),
+            InputSource::JoystickButton(b) => write!(f, "joystick_button:{}", b),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InputTarget
+{
+    Action(Action),
+    Axis(Axis, f64), // direction applied to the source value
+}
+
+// Shaping applied to a source value before it reaches the target
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct AxisSettings
+{
+    pub dead_zone: f64, // analog only, fraction of travel ignored around center
+    pub curve: f64,     // response exponent, 1 is linear
+    pub scale: f64,
+    pub invert: bool,
+}
+
+impl Default for AxisSettings
+{
+    fn default() -> Self
+    {
+        Self
+        {
+            dead_zone: 0.0,
+            curve: 1.0,
+            scale: 1.0,
+            invert: false,
+        }
+    }
+}
+
+impl AxisSettings
+{
+    pub fn apply(&self, value: f64, analog: bool) -> f64
+    {
+        let mut v = value;
+        if analog {
+            // rescale so output starts at zero on the edge of the dead zone
+            let a = v.abs();
+            v = if a <= self.dead_zone {0.0}
+                else {v.signum() * ((a - self.dead_zone) / (1.0 - self.dead_zone)).min(1.0)};
+            v = v.signum() * v.abs().powf(self.curve);
+        }
+        if self.invert { v = -v; }
+        v * self.scale
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Binding
+{
+    pub source: InputSource,
+    pub target: InputTarget,
+    pub settings: AxisSettings,
+}
+
+// Binding file, one binding per line, # starts a comment
+//   axis   <name>[+|-] <source> [dead_zone=f] [curve=f] [scale=f] [invert]
+//   action <name>      <source> [options]
+// sources: key:<sdl name>, mouse:x|y|left|middle|right|x1|x2,
+//   controller_axis:<sdl name>, controller_button:<sdl name>,
+//   joystick_axis:<n>, joystick_button:<n>
+#[derive(Clone, Debug, Default)]
+pub struct Bindings
+{
+    pub bindings: Vec<Binding>,
+}
+
+impl Bindings
+{
+
+    pub fn new() -> Self
+    {
+        Self {..Default::default()}
+    }
+
+    pub fn load(path: &str) -> Result<Self, String>
+    {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::parse(&text)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String>
+    {
+        std::fs::write(path, self.to_string()).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String>
+    {
+        let mut bindings = Self::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {continue;}
+            let binding = Self::parse_line(line).map_err(|e| format!("line {}: {}", n + 1, e))?;
+            bindings.bindings.push(binding);
+        }
+        Ok(bindings)
+    }
+
+    pub fn parse_line(line: &str) -> Result<Binding, String>
+    {
+        let mut tokens = line.split_whitespace();
+        let kind = tokens.next().ok_or("empty binding")?;
+        let name = tokens.next().ok_or("missing target")?;
+        let source = InputSource::from_string(tokens.next().ok_or("missing source")?)?;
+        let target = match kind {
+            "action" => InputTarget::Action(Action::from_name(name).ok_or(format!("unknown action {}", name))?),
+            "axis" => {
+                let (name, dir) = match name.strip_suffix('+') {
+                    Some(n) => (n, 1.0),
+                    None => match name.strip_suffix('-') {
+                        Some(n) => (n, -1.0),
+                        None => (name, 1.0),
+                    },
+                };
+                InputTarget::Axis(Axis::from_name(name).ok_or(format!("unknown axis {}", name))?, dir)
+            }
+            _ => {return Err(format!("unknown binding kind {}", kind));}
+        };
+        let mut settings = AxisSettings::default();
+        for option in tokens {
+            let (key, value) = option.split_once('=').unwrap_or((option, ""));
+            let parse = || value.parse::<f64>().map_err(|_| format!("bad value {}", option));
+            match key {
+                "dead_zone" => settings.dead_zone = parse()?.clamp(0.0, 0.99),
+                "curve" => settings.curve = parse()?.max(0.01),
+                "scale" => settings.scale = parse()?,
+                "invert" => settings.invert = true,
+                _ => {return Err(format!("unknown option {}", option));}
+            }
+        }
+        Ok(Binding{source, target, settings})
+    }
+
+    // replaces any binding of the same source to the same target
+    pub fn bind(&mut self, source: InputSource, target: InputTarget, settings: AxisSettings)
+    {
+        self.unbind_source(source, target);
+        self.bindings.push(Binding{source, target, settings});
+    }
+
+    pub fn unbind_source(&mut self, source: InputSource, target: InputTarget)
+    {
+        self.bindings.retain(|b| ! (b.source == source && Self::same_target(b.target, target)));
+    }
+
+    pub fn unbind(&mut self, target: InputTarget)
+    {
+        self.bindings.retain(|b| ! Self::same_target(b.target, target));
+    }
+
+    // axis targets match on axis and direction sign
+    pub fn same_target(a: InputTarget, b: InputTarget) -> bool
+    {
+        match (a, b) {
+            (InputTarget::Action(a), InputTarget::Action(b)) => a == b,
+            (InputTarget::Axis(a, da), InputTarget::Axis(b, db)) => a == b && (da < 0.0) == (db < 0.0),
+            _ => false,
+        }
+    }
+
+}
+
+// the file format read by parse
+impl std::fmt::Display for Bindings
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+    {
+        let default = AxisSettings::default();
+        for b in self.bindings.iter() {
+            match b.target {
+                InputTarget::Action(a) => write!(f, "action {}", a.name())?,
+                InputTarget::Axis(a, dir) if dir < 0.0 => write!(f, "axis {}-", a.name())?,
+                InputTarget::Axis(a, _) => write!(f, "axis {}", a.name())?,
+            }
+            write!(f, " {}", b.source)?;
+            let s = &b.settings;
+            if s.dead_zone != default.dead_zone { write!(f, " dead_zone={}", s.dead_zone)?; }
+            if s.curve != default.curve { write!(f, " curve={}", s.curve)?; }
+            if s.scale != default.scale { write!(f, " scale={}", s.scale)?; }
+            if s.invert { write!(f, " invert")?; }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+pub const DEFAULT_BINDINGS: &str = "\
+# spectator
+axis move_z+ key:W
+axis move_z- key:S
+axis move_x- key:A
+axis move_x+ key:D
+axis move_y+ key:Space
+axis move_y- key:Left_Shift
+axis look_roll- key:Q
+axis look_roll+ key:E
+axis look_x mouse:x
+axis look_y mouse:y
+axis move_x controller_axis:leftx dead_zone=0.15
+axis move_z controller_axis:lefty dead_zone=0.15 invert
+axis look_x controller_axis:rightx dead_zone=0.15 curve=2 scale=500
+axis look_y controller_axis:righty dead_zone=0.15 curve=2 scale=500
+# flight
+axis throttle+ key:W
+axis throttle- key:S
+axis yaw- key:A
+axis yaw+ key:D
+axis roll- key:Q
+axis roll+ key:E
+axis pitch+ key:Down
+axis pitch- key:Up
+axis roll- key:Left
+axis roll+ key:Right
+axis pitch mouse:y scale=0.05
+axis roll mouse:x scale=0.05
+axis roll controller_axis:leftx dead_zone=0.1 curve=1.5
+axis pitch controller_axis:lefty dead_zone=0.1 curve=1.5
+axis yaw controller_axis:rightx dead_zone=0.15
+axis throttle+ controller_axis:righttrigger dead_zone=0.05
+axis throttle- controller_axis:lefttrigger dead_zone=0.05
+axis roll joystick_axis:0 dead_zone=0.05 curve=1.5
+axis pitch joystick_axis:1 dead_zone=0.05 curve=1.5
+axis yaw joystick_axis:3 dead_zone=0.1
+axis throttle joystick_axis:2 invert
+# orbit camera, kept off the flight and look controls
+axis camera_yaw- key:J
+axis camera_yaw+ key:L
+axis camera_pitch+ key:I
+axis camera_pitch- key:K
+axis camera_yaw- controller_button:dpleft
+axis camera_yaw+ controller_button:dpright
+axis camera_pitch+ controller_button:dpup
+axis camera_pitch- controller_button:dpdown
+# actions
+action fire mouse:left
+action fire controller_button:a
+action fire joystick_button:0
+action camera_switch key:C
+action camera_switch controller_button:y
+action camera_switch joystick_button:1
+action toggle_mode key:F
+action toggle_mode controller_button:start
+action zoom key:Z
+action zoom mouse:right
+action zoom controller_button:leftshoulder
+# debug
+action pause key:P
+action step key:.
+action slow_down key:[
+action speed_up key:]
+action toggle_hud key:F3
+";
+
+//}}}
+
+//{{{ InputManager
+
+// Collects device state from sdl events, evaluates the bindings once per update
+pub struct InputManager
+{
+    pub bindings: Bindings,
+    pub keys: HashSet<Keycode>,
+    pub mouse_buttons: HashSet<MouseButton>,
+    pub mouse_delta: IVec2,
+    // keyed by device instance id
+    pub controller_axes: HashMap<(u32, controller::Axis), f64>,
+    pub controller_buttons: HashSet<(u32, controller::Button)>,
+    pub joystick_axes: HashMap<(u32, u8), f64>,
+    pub joystick_buttons: HashSet<(u32, u8)>,
+    pub rebind: Option<InputTarget>, // next input becomes a binding for this
+    pub state: InputState,           // latest frame
+    pub pending: InputState,         // accumulated until the next tick takes it
+    pub relative: [f64; AXIS_COUNT], // bounded axes, mouse motion since the last tick, added on take
+    // devices stay open while held
+    pub controller_subsystem: Option<GameControllerSubsystem>,
+    pub joystick_subsystem: Option<JoystickSubsystem>,
+    pub controllers: Vec<GameController>,
+    pub joysticks: Vec<Joystick>,
+}
+
+impl Default for InputManager
+{
+    fn default() -> Self
+    {
+        Self
+        {
+            bindings: Bindings::parse(DEFAULT_BINDINGS).unwrap(),
+            keys: HashSet::new(),
+            mouse_buttons: HashSet::new(),
+            mouse_delta: IVec2::ZERO,
+            controller_axes: HashMap::new(),
+            controller_buttons: HashSet::new(),
+            joystick_axes: HashMap::new(),
+            joystick_buttons: HashSet::new(),
+            rebind: None,
+            state: InputState::default(),
+            pending: InputState::default(),
+            relative: [0.0; AXIS_COUNT],
+            controller_subsystem: None,
+            joystick_subsystem: None,
+            controllers: vec![],
+            joysticks: vec![],
+        }
+    }
+}
+
+impl InputManager
+{
+
+    pub fn new() -> Self
+    {
+        Self {..Default::default()}
+    }
+
+    // falls back to the defaults when the file is missing
+    pub fn with_bindings_file(path: &str) -> Result<Self, String>
+    {
+        let mut input = Self::new();
+        if std::path::Path::new(path).exists() {
+            input.bindings = Bindings::load(path)?;
+        }
+        Ok(input)
+    }
+
+    // opens devices already connected, later ones arrive as events
+    pub fn attach(&mut self, sdl: &Sdl) -> Result<(), String>
+    {
+        let joystick = sdl.joystick()?;
+        let controller = sdl.game_controller()?;
+        for i in 0 .. joystick.num_joysticks()? {
+            self.open_device(&controller, &joystick, i);
+        }
+        self.controller_subsystem = Some(controller);
+        self.joystick_subsystem = Some(joystick);
+        Ok(())
+    }
+
+    pub fn open_device(&mut self, controller: &GameControllerSubsystem, joystick: &JoystickSubsystem, index: u32)
+    {
+        if controller.is_game_controller(index) {
+            if let Ok(c) = controller.open(index) {
+                if ! self.controllers.iter().any(|o| o.instance_id() == c.instance_id()) {
+                    self.controllers.push(c);
+                }
+            }
+        }
+        else if let Ok(j) = joystick.open(index) {
+            if ! self.joysticks.iter().any(|o| o.instance_id() == j.instance_id()) {
+                self.joysticks.push(j);
+            }
+        }
+    }
+
+    pub fn is_controller(&self, instance_id: u32) -> bool
+    {
+        self.controllers.iter().any(|c| c.instance_id() == instance_id)
+    }
+
+    pub fn start_rebind(&mut self, target: InputTarget)
+    {
+        self.rebind = Some(target);
+    }
+
+    fn capture_rebind(&mut self, source: InputSource)
+    {
+        if let Some(target) = self.rebind.take() {
+            self.bindings.bind(source, target, AxisSettings::default());
+        }
+    }
+
+    pub fn handle_event(&mut self, event: &Event)
+    {
+        match *event {
+            Event::KeyDown{keycode: Some(k), repeat: false, ..} => {
+                self.capture_rebind(InputSource::Key(k));
+            }
+            Event::MouseButtonDown{mouse_btn, ..} => {
+                self.capture_rebind(InputSource::MouseButton(mouse_btn));
+            }
+            Event::ControllerDeviceAdded{which, ..} | Event::JoyDeviceAdded{which, ..} => {
+                if let (Some(c), Some(j)) = (self.controller_subsystem.clone(), self.joystick_subsystem.clone()) {
+                    self.open_device(&c, &j, which);
+                }
+            }
+            Event::ControllerDeviceRemoved{which, ..} => {
+                self.controllers.retain(|c| c.instance_id() != which);
+                self.controller_axes.retain(|(id, _), _| *id != which);
+                self.controller_buttons.retain(|(id, _)| *id != which);
+            }
+            Event::JoyDeviceRemoved{which, ..} => {
+                self.joysticks.retain(|j| j.instance_id() != which);
+                self.joystick_axes.retain(|(id, _), _| *id != which);
+                self.joystick_buttons.retain(|(id, _)| *id != which);
+            }
+            Event::ControllerAxisMotion{which, axis, value, ..} => {
+                let v = (value as f64 / i16::MAX as f64).clamp(-1.0, 1.0);
+                if v.abs() > 0.5 { self.capture_rebind(InputSource::ControllerAxis(axis)); }
+                self.controller_axes.insert((which, axis), v);
+            }
+            Event::ControllerButtonDown{which, button, ..} => {
+                self.capture_rebind(InputSource::ControllerButton(button));
+                self.controller_buttons.insert((which, button));
+            }
+            Event::ControllerButtonUp{which, button, ..} => {
+                self.controller_buttons.remove(&(which, button));
+            }
+            // game controllers also report as joysticks, those are handled above
+            Event::JoyAxisMotion{which, axis_idx, value, ..} if ! self.is_controller(which) => {
+                let v = (value as f64 / i16::MAX as f64).clamp(-1.0, 1.0);
+                if v.abs() > 0.5 { self.capture_rebind(InputSource::JoystickAxis(axis_idx)); }
+                self.joystick_axes.insert((which, axis_idx), v);
+            }
+            Event::JoyButtonDown{which, button_idx, ..} if ! self.is_controller(which) => {
+                self.capture_rebind(InputSource::JoystickButton(button_idx));
+                self.joystick_buttons.insert((which, button_idx));
+            }
+            Event::JoyButtonUp{which, button_idx, ..} if ! self.is_controller(which) => {
+                self.joystick_buttons.remove(&(which, button_idx));
+            }
+            _ => {}
+        }
+    }
+
+    // with several devices connected the one pushed furthest wins
+    pub fn source_value(&self, source: InputSource) -> f64
+    {
+        let digital = |b: bool| if b {1.0} else {0.0};
+        let furthest = |v: f64, m: f64| if v.abs() > m.abs() {v} else {m};
+        match source {
+            InputSource::Key(k) => digital(self.keys.contains(&k)),
+            InputSource::MouseButton(b) => digital(self.mouse_buttons.contains(&b)),
+            InputSource::MouseAxis(0) => self.mouse_delta.x as f64,
+            InputSource::MouseAxis(_) => self.mouse_delta.y as f64,
+            InputSource::ControllerAxis(a) => self.controller_axes.iter()
+                .filter(|((_, x), _)| *x == a).fold(0.0, |m, (_, v)| furthest(*v, m)),
+            InputSource::ControllerButton(b) => digital(self.controller_buttons.iter().any(|(_, x)| *x == b)),
+            InputSource::JoystickAxis(a) => self.joystick_axes.iter()
+                .filter(|((_, x), _)| *x == a).fold(0.0, |m, (_, v)| furthest(*v, m)),
+            InputSource::JoystickButton(b) => digital(self.joystick_buttons.iter().any(|(_, x)| *x == b)),
+        }
+    }
+
+    // keyboard and mouse are polled per frame, the rest comes from handle_event
+    // analog sources on unbounded axes are integrated over elapsed_time
+    pub fn update(&mut self, keys: HashSet<Keycode>, mouse_buttons: HashSet<MouseButton>, mouse_delta: IVec2, elapsed_time: f64) -> InputState
+    {
+        self.keys = keys;
+        self.mouse_buttons = mouse_buttons;
+        self.mouse_delta = mouse_delta;
+
+        let mut state = InputState::default();
+        let mut absolute = [0.0; AXIS_COUNT];
+        for b in self.bindings.bindings.iter() {
+            let mut value = b.settings.apply(self.source_value(b.source), b.source.is_analog());
+            match b.target {
+                InputTarget::Action(a) => if value > 0.5 { state.held |= a.bit(); },
+                InputTarget::Axis(a, dir) => {
+                    if ! a.bounded() && b.source.is_analog() { value *= elapsed_time; }
+                    state.axes[a as usize] += value * dir;
+                    if a.bounded() && b.source.is_relative() { self.relative[a as usize] += value * dir; }
+                    else { absolute[a as usize] += value * dir; }
+                }
+            }
+        }
+        for a in Axis::ALL.iter() {
+            if a.bounded() {
+                let v = &mut state.axes[*a as usize];
+                *v = v.clamp(-1.0, 1.0);
+            }
+        }
+        state.pressed = state.held & ! self.state.held;
+        self.state = state;
+
+        // frames and ticks don't line up, keep edges and motion until a tick runs
+        // sticks and keys only count as of the latest frame, mouse motion adds up
+        for a in Axis::ALL.iter() {
+            let i = *a as usize;
+            if a.bounded() { self.pending.axes[i] = absolute[i]; }
+            else { self.pending.axes[i] += state.axes[i]; }
+        }
+        self.pending.held = state.held;
+        self.pending.pressed |= state.pressed;
+        state
+    }
+
+    // input for one tick, later ticks in the same frame see no new edges or motion
+    // bounded axes are clamped once the tick's mouse motion is in
+    pub fn take(&mut self) -> InputState
+    {
+        let mut tick = self.pending;
+        for a in Axis::ALL.iter() {
+            let i = *a as usize;
+            if a.bounded() { tick.axes[i] = (tick.axes[i] + self.relative[i]).clamp(-1.0, 1.0); }
+            else { self.pending.axes[i] = 0.0; }
+        }
+        self.pending.pressed = 0;
+        self.relative = [0.0; AXIS_COUNT];
+        tick
+    }
+
+}
+
+//}}}
+
+//{{{ VirtualJoystick
+
+// SDL virtual joystick, shows up as a regular device (for testing bindings without hardware)
+pub struct VirtualJoystick
+{
+    pub device_index: i32,
+    pub joystick: Joystick,
+}
+
+impl VirtualJoystick
+{
+
+    pub fn attach(subsystem: &JoystickSubsystem, axes: i32, buttons: i32) -> Result<Self, String>
+    {
+        let device_index = unsafe {
+            sdl2::sys::SDL_JoystickAttachVirtual(
+                sdl2::sys::SDL_JoystickType::SDL_JOYSTICK_TYPE_FLIGHT_STICK,
+                axes, buttons, 0,
+            )
+        };
+        if device_index < 0 {return Err(sdl2::get_error());}
+        let joystick = subsystem.open(device_index as u32).map_err(|e| e.to_string())?;
+        Ok(Self{device_index, joystick})
+    }
+
+    fn raw(&self) -> *mut sdl2::sys::SDL_Joystick
+    {
+        unsafe { sdl2::sys::SDL_JoystickFromInstanceID(self.joystick.instance_id() as i32) }
+    }
+
+    // events for the change are generated on the next event pump
+    pub fn set_axis(&self, axis: i32, value: i16) -> Result<(), String>
+    {
+        let r = unsafe { sdl2::sys::SDL_JoystickSetVirtualAxis(self.raw(), axis, value) };
+        if r < 0 {Err(sdl2::get_error())} else {Ok(())}
+    }
+
+    pub fn set_button(&self, button: i32, pressed: bool) -> Result<(), String>
+    {
+        let r = unsafe { sdl2::sys::SDL_JoystickSetVirtualButton(self.raw(), button, pressed as u8) };
+        if r < 0 {Err(sdl2::get_error())} else {Ok(())}
+    }
+
+    pub fn detach(self) -> Result<(), String>
+    {
+        let index = self.device_index;
+        drop(self.joystick);
+        let r = unsafe { sdl2::sys::SDL_JoystickDetachVirtual(index) };
+        if r < 0 {Err(sdl2::get_error())} else {Ok(())}
+    }
+
+    // Joystick script -- moves a new virtual joystick, ticks come out of the bindings
+    // like they would from a real stick, one block per line, # starts a comment
+    //   <ticks> [axis_index=value ...] [button_index ...]
+    // axes keep their value until set again, buttons are held for the block
+    //   60  2=-1      # throttle up
+    //   120 1=-0.3 0  # nose up and fire
+    pub fn run_script(text: &str, bindings: Bindings, tick_rate: f64) -> Result<Vec<InputState>, String>
+    {
+        sdl2::hint::set("SDL_JOYSTICK_ALLOW_BACKGROUND_EVENTS", "1");
+        let sdl = sdl2::init()?;
+        let subsystem = sdl.joystick()?;
+        let mut events = sdl.event_pump()?;
+        let stick = Self::attach(&subsystem, 6, 16)?;
+        let mut input = InputManager::new();
+        input.bindings = bindings;
+        input.attach(&sdl)?;
+
+        let mut ticks = vec![];
+        let mut held: Vec<i32> = vec![];
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {continue;}
+            let err = |e: String| format!("line {}: {}", n + 1, e);
+            let mut tokens = line.split_whitespace();
+            let count: usize = tokens.next().unwrap().parse().map_err(|_| err("bad tick count".to_string()))?;
+            let mut buttons = vec![];
+            for token in tokens {
+                if let Some((axis, value)) = token.split_once('=') {
+                    let axis: i32 = axis.parse().map_err(|_| err(format!("bad axis {}", token)))?;
+                    let value: f64 = value.parse().map_err(|_| err(format!("bad value {}", token)))?;
+                    stick.set_axis(axis, (value.clamp(-1.0, 1.0) * i16::MAX as f64) as i16).map_err(err)?;
+                }
+                else {
+                    buttons.push(token.parse::<i32>().map_err(|_| err(format!("bad button {}", token)))?);
+                }
+            }
+            for b in held.iter().filter(|b| ! buttons.contains(b)) { stick.set_button(*b, false).map_err(err)?; }
+            for b in buttons.iter() { stick.set_button(*b, true).map_err(err)?; }
+            held = buttons;
+            // one frame per tick, so every tick sees the stick as the script left it
+            for _ in 0 .. count {
+                for event in events.poll_iter() { input.handle_event(&event); }
+                input.update(HashSet::new(), HashSet::new(), IVec2::ZERO, 1.0 / tick_rate);
+                ticks.push(input.take());
+            }
+        }
+        drop(input);
+        stick.detach()?;
+        Ok(ticks)
+    }
+
+}
+
+//}}}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn manager(bindings: &str) -> InputManager
+    {
+        let mut input = InputManager::new();
+        input.bindings = Bindings::parse(bindings).unwrap();
+        input
+    }
+
+    fn frame(input: &mut InputManager, keys: &[Keycode], mouse: IVec2) -> InputState
+    {
+        input.update(keys.iter().copied().collect(), HashSet::new(), mouse, 1.0 / 60.0)
+    }
+
+    fn axis_motion(which: u32, axis: controller::Axis, value: i16) -> Event
+    {
+        Event::ControllerAxisMotion{timestamp: 0, which, axis, value}
+    }
+
+    #[test]
+    fn bindings_round_trip()
+    {
+        let defaults = Bindings::parse(DEFAULT_BINDINGS).unwrap();
+        let text = defaults.to_string();
+        assert_eq!(Bindings::parse(&text).unwrap().bindings, defaults.bindings);
+
+        let line = "axis pitch- joystick_axis:1 dead_zone=0.1 curve=2 scale=0.5 invert\n\
+                    action fire controller_button:a\n\
+                    axis move_y key:Left_Shift\n";
+        assert_eq!(Bindings::parse(line).unwrap().to_string(), line);
+    }
+
+    #[test]
+    fn bindings_errors()
+    {
+        assert!(Bindings::parse("axis warp key:W").is_err());
+        assert!(Bindings::parse("action fly key:W").is_err());
+        assert!(Bindings::parse("axis pitch key:NotAKey").is_err());
+        assert!(Bindings::parse("axis pitch mouse:z").is_err());
+        assert!(Bindings::parse("axis pitch key:W curve=steep").is_err());
+        assert!(Bindings::parse("bind pitch key:W").is_err());
+    }
+
+    #[test]
+    fn keys_and_actions()
+    {
+        let mut input = manager("axis move_z+ key:W\naxis move_z- key:S\naction fire key:Space\n");
+        let state = frame(&mut input, &[Keycode::W, Keycode::Space], IVec2::ZERO);
+        assert_eq!(state.axis(Axis::MoveZ), 1.0);
+        assert!(state.held(Action::Fire) && state.pressed(Action::Fire));
+        let state = frame(&mut input, &[Keycode::W, Keycode::S, Keycode::Space], IVec2::ZERO);
+        assert_eq!(state.axis(Axis::MoveZ), 0.0);
+        assert!(state.held(Action::Fire) && ! state.pressed(Action::Fire));
+    }
+
+    #[test]
+    fn controller_axes()
+    {
+        let mut input = manager("axis roll controller_axis:leftx dead_zone=0.2\naxis pitch controller_axis:lefty invert\n");
+        input.handle_event(&axis_motion(1, controller::Axis::LeftX, i16::MAX / 10));
+        input.handle_event(&axis_motion(1, controller::Axis::LeftY, i16::MAX / 2));
+        let state = frame(&mut input, &[], IVec2::ZERO);
+        assert_eq!(state.axis(Axis::Roll), 0.0); // inside the dead zone
+        assert!((state.axis(Axis::Pitch) + 0.5).abs() < 1e-3);
+
+        // the device pushed furthest wins, removing one leaves the other
+        input.handle_event(&axis_motion(2, controller::Axis::LeftY, -i16::MAX));
+        assert!((frame(&mut input, &[], IVec2::ZERO).axis(Axis::Pitch) - 1.0).abs() < 1e-3);
+        input.handle_event(&Event::ControllerDeviceRemoved{timestamp: 0, which: 2});
+        assert!((frame(&mut input, &[], IVec2::ZERO).axis(Axis::Pitch) + 0.5).abs() < 1e-3);
+        input.handle_event(&Event::ControllerDeviceRemoved{timestamp: 0, which: 1});
+        assert_eq!(frame(&mut input, &[], IVec2::ZERO).axis(Axis::Pitch), 0.0);
+    }
+
+    #[test]
+    fn mouse_accumulates_until_taken()
+    {
+        let mut input = manager("axis pitch mouse:y scale=0.1\naxis look_x mouse:x\n");
+        frame(&mut input, &[], ivec2(2, 3));
+        frame(&mut input, &[], ivec2(5, 4));
+        let tick = input.take();
+        assert!((tick.axis(Axis::Pitch) - 0.7).abs() < 1e-9);
+        assert_eq!(tick.axis(Axis::LookX), 7.0);
+        // a second tick in the same frame sees no motion
+        let tick = input.take();
+        assert_eq!(tick.axis(Axis::Pitch), 0.0);
+        assert_eq!(tick.axis(Axis::LookX), 0.0);
+        // clamped per tick
+        frame(&mut input, &[], ivec2(0, 30));
+        assert_eq!(input.take().axis(Axis::Pitch), 1.0);
+    }
+
+    #[test]
+    fn rebind_captures_next_input()
+    {
+        let mut input = manager("");
+        input.start_rebind(InputTarget::Action(Action::Zoom));
+        input.handle_event(&Event::ControllerButtonDown{timestamp: 0, which: 0, button: controller::Button::X});
+        assert_eq!(input.bindings.bindings.len(), 1);
+        assert_eq!(input.bindings.bindings[0].source, InputSource::ControllerButton(controller::Button::X));
+        assert!(frame(&mut input, &[], IVec2::ZERO).held(Action::Zoom));
+    }
+
+    #[test]
+    fn virtual_joystick_script()
+    {
+        let bindings = Bindings::parse("axis pitch joystick_axis:1\naction fire joystick_button:0\n").unwrap();
+        let ticks = VirtualJoystick::run_script("2 1=0.5 0\n2 1=-1\n", bindings, 60.0).unwrap();
+        assert_eq!(ticks.len(), 4);
+        assert!((ticks[1].axis(Axis::Pitch) - 0.5).abs() < 1e-3);
+        assert!(ticks[1].held(Action::Fire));
+        assert!((ticks[3].axis(Axis::Pitch) + 1.0).abs() < 1e-3);
+        assert!(! ticks[3].held(Action::Fire));
+    }
+}
@@ -6,8 +6,10 @@
 #![allow(unused_must_use)]
 
 use std::collections::HashSet;
+#[cfg(feature = "window")]
 use pollster::FutureExt as _;
 use glam::*;
+#[cfg(feature = "window")]
 use sdl2::{
     Sdl, EventPump,
     event::{Event, WindowEvent},
@@ -16,24 +18,48 @@ use sdl2::{
     mouse::*,
 };
 
+#[cfg(feature = "window")]
 pub mod gpu;
 pub mod render;
 pub mod world;
 pub mod math;
 pub mod game;
 pub mod player;
+pub mod camera;
+pub mod input;
+pub mod clock;
+pub mod replay;
+pub mod headless;
+pub mod bench;
 
-use crate::gpu::Gpu;
+#[cfg(feature = "window")]
+use crate::gpu::{Gpu, GpuFault};
 use crate::game::Game;
+#[cfg(feature = "window")]
+use crate::input::{InputManager, Action};
+#[cfg(feature = "window")]
+use crate::render::{hud::Hud, terrain::TerrainConfig};
+use crate::clock::FixedClock;
+use crate::replay::Recording;
+use crate::bench::Benchmark;
 
+#[cfg(feature = "window")]
 pub struct App {
     pub sdl_context: Sdl,
     pub title: String,
     pub events: EventPump,
     pub window: Window,
     pub game: Game,
+    pub input: InputManager,
+    pub clock: FixedClock,
+    pub recording: Option<(String, Recording)>, // output path
+    pub bench: Option<(Benchmark, String)>,     // output prefix
+    pub hud: Hud,
+    pub terrain: TerrainConfig,
+    pub gpu_fault: Option<GpuFault>, // forced every second, a lost device once it recovered
 }
 
+#[cfg(feature = "window")]
 impl App {
 
     pub fn new(title: String, width: Option<u32>, height: Option<u32>) -> Result<Self, String> {
@@ -48,6 +74,8 @@ impl App {
             .build()
             .map_err(|e| e.to_string())?;
         let game = Game::new();
+        let mut input = InputManager::with_bindings_file("./bindings.cfg")?;
+        input.attach(&sdl_context)?;
         sdl_context.mouse().set_relative_mouse_mode(true);
         Ok(Self {
             sdl_context,
@@ -55,18 +83,53 @@ impl App {
             events,
             window,
             game,
+            input,
+            clock: FixedClock::new(60.0),
+            recording: None,
+            bench: None,
+            hud: Hud::default(),
+            terrain: TerrainConfig::default(),
+            gpu_fault: None,
         })
     }
 
+    pub fn record(&mut self, path: String) {
+        let recording = Recording::new(self.game.world.seed(), self.clock.tick_rate);
+        self.recording = Some((path, recording));
+    }
+
+    pub fn save_recording(&mut self) -> Result<(), String> {
+        if let Some((path, mut recording)) = self.recording.take() {
+            recording.finish(&self.game);
+            recording.save(&path)?;
+            println!("recorded {} ticks to {}", recording.ticks.len(), path);
+        }
+        Ok(())
+    }
+
+    pub fn finish_benchmark(&mut self) -> Result<(), String> {
+        if let Some((bench, out)) = self.bench.take() {
+            println!("{}", bench.report());
+            bench.write(&out)?;
+        }
+        Ok(())
+    }
+
     pub async fn run(&mut self) -> Result<(), String> {
 
-        let mut gpu = Gpu::new(&self.window).await;
+        let mut gpu = Gpu::new(&self.window, self.terrain).await;
 
+        let (width, height) = self.window.size();
+        self.game.resize(width, height);
         self.game.initialize();
+        if let Some((bench, _)) = &mut self.bench {
+            bench.prepare(&mut self.game);
+        }
         let mut timer = std::time::Instant::now();
         let mut fps_avg = 0.0;
-        let mut prev_keys = HashSet::new();
-        let mut orig_pos = ivec2(400, 300);
+        let mut stats_timer = std::time::Instant::now();
+        let mut fault_timer = std::time::Instant::now();
+        let mut orig_pos = ivec2(width as i32 / 2, height as i32 / 2);
         self.sdl_context.mouse().warp_mouse_in_window(&self.window, orig_pos.x, orig_pos.y);
 
         'running: loop {
@@ -77,6 +140,7 @@ impl App {
             self.window.set_title(format!("{} // FPS {}", self.title, fps_avg as i32).as_str());
 
             for event in self.events.poll_iter() {
+                self.input.handle_event(&event);
                 match event {
                     Event::Window {
                         window_id,
@@ -84,12 +148,31 @@ impl App {
                         ..
                     } if window_id == self.window.id() => {
                         gpu.resize(width as u32, height as u32);
+                        self.game.resize(width as u32, height as u32);
                         orig_pos = ivec2(width / 2, height / 2);
                     }
+                    Event::Window {
+                        window_id,
+                        win_event: WindowEvent::Minimized,
+                        ..
+                    } if window_id == self.window.id() => {
+                        gpu.resize(0, 0);
+                    }
+                    // not every platform sends a size change on restore
+                    Event::Window {
+                        window_id,
+                        win_event: WindowEvent::Restored | WindowEvent::Maximized,
+                        ..
+                    } if window_id == self.window.id() => {
+                        let (width, height) = self.window.size();
+                        gpu.resize(width, height);
+                        self.game.resize(width, height);
+                        orig_pos = ivec2(width as i32 / 2, height as i32 / 2);
+                    }
                     Event::Quit { .. } | Event::KeyDown {
                         keycode: Some(Keycode::Escape),
                     .. } => {
-                        break 'running Ok(());
+                        break 'running self.save_recording();
                     }
                     e => {
                         // dbg!(e);
@@ -103,33 +186,129 @@ impl App {
                 .pressed_scancodes()
                 .filter_map(Keycode::from_scancode)
                 .collect();
-            // Get the difference between the new and old sets.
-            let new_keys = &keys - &prev_keys;
-            let old_keys = &prev_keys - &keys;
-            
+
             let mouse = self.events.mouse_state();
+            let buttons = mouse.pressed_mouse_buttons().collect();
             let pos = ivec2(mouse.x(), mouse.y());
             let change_pos = pos - orig_pos;
-            println!("pos: {:?}", change_pos);
             self.sdl_context.mouse().warp_mouse_in_window(&self.window, orig_pos.x, orig_pos.y);
 
-            self.game.update(elapsed_seconds, &keys, change_pos)?;
-            prev_keys = keys;
+            let input = self.input.update(keys, buttons, change_pos, elapsed_seconds as f64);
+            if input.pressed(Action::ToggleHud) { self.hud.toggle(); }
+
+            // nothing to draw into, the game waits and the clock drops the gap
+            // end_frame isn't called so chunk updates carry over to the next drawn frame
+            if gpu.minimized {
+                self.input.take();
+                std::thread::sleep(std::time::Duration::from_millis(16));
+                continue;
+            }
+
+            // recovery from a forced device loss is checked on every drawn frame, benchmark included
+            if let Err(err) = gpu.check_recovery() {
+                self.save_recording()?;
+                break 'running Err(err);
+            }
+            if let Some(fault) = self.gpu_fault {
+                if fault_timer.elapsed().as_secs_f64() >= 1.0 && ! gpu.is_recovering() {
+                    fault_timer = std::time::Instant::now();
+                    log::warn!("forcing {:?}", fault);
+                    gpu.force_error(fault);
+                }
+            }
+
+            // benchmark drives the player, one tick per frame
+            if let Some((bench, _)) = &mut self.bench {
+                bench.step(&mut self.game)?;
+                self.hud.update(elapsed_seconds as f64, &self.game, gpu.buffer_stats());
+                let gamedata = self.game.get_gamedata();
+                if let Err(err) = gpu.render(&gamedata, &self.hud) {
+                    break 'running Err(format!("render failed: {}", err));
+                }
+                let stages = self.game.world.end_frame();
+                bench.record(stages, Some(gpu.upload_time()), self.game.world.chunk_stats());
+                if bench.done() {
+                    break 'running self.finish_benchmark();
+                }
+                continue;
+            }
+
+            // debug clock controls
+            if input.pressed(Action::Pause) { self.clock.toggle_pause(); }
+            if input.pressed(Action::Step) { self.clock.step(); }
+            if input.pressed(Action::SlowDown) { self.clock.scale_time(0.5); }
+            if input.pressed(Action::SpeedUp) { self.clock.scale_time(2.0); }
+
+            let steps = self.clock.advance(elapsed_seconds as f64);
+            if self.clock.paused && steps == 0 {
+                // don't queue up input while paused
+                self.input.take();
+            }
+            for _ in 0 .. steps {
+                let tick = self.input.take();
+                if let Some((_, recording)) = &mut self.recording {
+                    recording.push(&tick);
+                }
+                self.game.update(self.clock.dt() as f32, &tick)?;
+            }
+            self.game.set_interpolation(self.clock.alpha());
 
             // game render
+            self.hud.update(elapsed_seconds as f64, &self.game, gpu.buffer_stats());
             let gamedata = self.game.get_gamedata();
 
-            gpu.render(&gamedata);
+            if let Err(err) = gpu.render(&gamedata, &self.hud) {
+                self.save_recording()?;
+                break 'running Err(format!("render failed: {}", err));
+            }
+            self.game.world.end_frame();
+
+            if stats_timer.elapsed().as_secs_f64() >= 1.0 {
+                stats_timer = std::time::Instant::now();
+                log::info!("{}", self.game.world.chunk_stats());
+                if let Some(stats) = gpu.buffer_stats() {
+                    log::info!("{}", stats);
+                }
+            }
         }
 
     }
 
 }
 
+// --record <file>  record the session input
+// --bench  flythrough benchmark, see headless::bench_from_args
+// --gpu-cull  compute culling, --vram <MiB>  chunk mesh memory budget
+// --gpu-fault <timeout|outdated|lost|device>  force a gpu failure to test recovery
+// --headless, --replay <file>  see headless::start
 pub fn start() -> Result<(), String> {
-    let title = String::from("SDFShader");
-    let mut app = App::new(title, None, None)?;
-    app.run().block_on()?;
+    let args: Vec<String> = std::env::args().collect();
+    if cfg!(not(feature = "window"))
+        || args.iter().any(|a| a == "--headless" || a == "--replay")
+    {
+        return headless::start(&args);
+    }
+
+    #[cfg(feature = "window")]
+    {
+        let arg = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1)).cloned();
+        let title = String::from("SDFShader");
+        let mut app = App::new(title, None, None)?;
+        if let Some(seed) = arg("--seed") {
+            app.game = Game::with_seed(seed.parse().map_err(|_| format!("bad --seed {}", seed))?);
+        }
+        if let Some(path) = arg("--record") {
+            app.record(path);
+        }
+        app.bench = headless::bench_from_args(&args)?;
+        app.terrain.gpu_culling = args.iter().any(|a| a == "--gpu-cull");
+        if let Some(name) = arg("--gpu-fault") {
+            app.gpu_fault = Some(GpuFault::parse(&name).ok_or(format!("bad --gpu-fault {}", name))?);
+        }
+        if let Some(mib) = arg("--vram") {
+            app.terrain.vram_budget = mib.parse::<u64>().map_err(|_| format!("bad --vram {}", mib))? << 20;
+        }
+        app.run().block_on()?;
+    }
     Ok(())
 }
-
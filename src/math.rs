@@ -5,6 +5,8 @@ pub mod hasher;
 pub mod octree;
 pub mod direction;
 pub mod generator;
+pub mod transform;
+pub mod frustum;
 
 // util functions {{{
 
@@ -113,6 +115,18 @@ pub fn mat_projection (fov: f64, ratio: f64, near: f64, far: f64) -> DMat4
 
 }
 
+// height in world units, reversed z (near 1, far 0), LH
+#[inline]
+pub fn mat_orthographic (height: f64, ratio: f64, near: f64, far: f64) -> DMat4
+{
+    dmat4(
+        dvec4(2.0 * ratio / height, 0.0,          0.0,                        0.0),
+        dvec4(0.0,                  2.0 / height, 0.0,                        0.0),
+        dvec4(0.0,                  0.0,          -1.0 / (far - near),        0.0),
+        dvec4(0.0,                  0.0,          far / (far - near),         1.0),
+    )
+}
+
 #[inline]
 pub fn mat_rotation_x (theta: f64) -> DMat4
 {
@@ -152,6 +166,24 @@ pub fn mat_rotation (theta: DVec3) -> DMat4
     mat_rotation_z(theta.z) * mat_rotation_y(theta.y) * mat_rotation_x(theta.x)
 }
 
+// same rotation as mat_rotation (note z is mirrored relative to glam)
+#[inline]
+pub fn quat_rotation (theta: DVec3) -> DQuat
+{
+    DQuat::from_rotation_z(-theta.z) * DQuat::from_rotation_y(theta.y) * DQuat::from_rotation_x(theta.x)
+}
+
+// rotation with +z along forward and +y towards up (left handed)
+#[inline]
+pub fn look_rotation (forward: DVec3, up: DVec3) -> DQuat
+{
+    let forward = forward.normalize();
+    let right = up.cross(forward).try_normalize()
+        .unwrap_or_else(|| forward.any_orthonormal_vector());
+    let up = forward.cross(right);
+    DQuat::from_mat3(&DMat3::from_cols(right, up, forward)).normalize()
+}
+
 #[inline]
 pub fn mat_translation (t: DVec3) -> DMat4
 {
@@ -222,31 +254,6 @@ pub fn to_dvec4 (vec: IVec4) -> DVec4 { dvec4(vec.x as f64, vec.y as f64, vec.z
 
 // coord {{{
     
-#[inline]
-pub fn key2coord(key: &SeaHashKey) -> IVec3
-{
-    ivec3(
-        i32::from_ne_bytes(key[0..4].try_into().unwrap()),
-        i32::from_ne_bytes(key[4..8].try_into().unwrap()),
-        i32::from_ne_bytes(key[8..12].try_into().unwrap()),
-    )
-}
-
-#[inline]
-pub fn coord2key(coord: IVec3) -> SeaHashKey
-{
-    let (x, y, z) = (
-        coord.x.to_ne_bytes(),
-        coord.y.to_ne_bytes(),
-        coord.z.to_ne_bytes(),
-    );
-    [
-        x[0], x[1], x[2], x[3],
-        y[0], y[1], y[2], y[3],
-        z[0], z[1], z[2], z[3],
-    ]
-}
-
 #[inline]
 pub fn coord2ind(coord: IVec3, size: i32) -> i32 {
     coord.x + (coord.y + coord.z * size) * size
@@ -300,14 +307,14 @@ pub fn chunk2pos(chunk_coord: IVec3, size: i32) -> DVec3 {
 }
 
 #[inline]
-pub fn key2mixed(key: SeaHashKey, size: i32) -> (IVec3, IVec3) {
-    coord2mixed(key2coord(&key), size)
+pub fn key2mixed(key: VoxelKey, size: i32) -> (IVec3, IVec3) {
+    coord2mixed(key.coord(), size)
 }
 
 #[inline]
-pub fn key2mixedkey(key: SeaHashKey, size: i32) -> (SeaHashKey, SeaHashKey) {
+pub fn key2mixedkey(key: VoxelKey, size: i32) -> (ChunkKey, VoxelKey) {
     let (a, b) = key2mixed(key, size);
-    (coord2key(a), coord2key(b))
+    (ChunkKey::new(a / size), VoxelKey::new(b))
 }
 
 #[inline]
@@ -321,5 +328,29 @@ pub fn coord2mixed(mixed_coord: IVec3, size: i32) -> (IVec3, IVec3) {
     (chunk, coord)
 }
 
+// encoding {{{
+
+// unit vector to the octahedron unfolded onto [-1, 1]^2
+#[inline]
+pub fn oct_encode(n: Vec3) -> Vec2 {
+    let n = n / (n.x.abs() + n.y.abs() + n.z.abs()).max(1e-20);
+    if n.z >= 0.0 {
+        n.truncate()
+    } else {
+        let sign = vec2(if n.x >= 0.0 {1.0} else {-1.0}, if n.y >= 0.0 {1.0} else {-1.0});
+        (Vec2::ONE - vec2(n.y, n.x).abs()) * sign
+    }
+}
+
+#[inline]
+pub fn oct_decode(e: Vec2) -> Vec3 {
+    let z = 1.0 - e.x.abs() - e.y.abs();
+    let t = (-z).max(0.0);
+    let xy = e - vec2(if e.x >= 0.0 {t} else {-t}, if e.y >= 0.0 {t} else {-t});
+    xy.extend(z).normalize()
+}
+
+//}}}
+
 // }}}
 
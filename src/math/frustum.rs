@@ -0,0 +1,50 @@
+use glam::*;
+
+// Frustum -- clip planes pulled out of a view projection matrix
+// clip space is x,y in -w..w and z in 0..w (reversed or not),
+// planes with no normal (infinite far) are dropped
+
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum
+{
+    pub planes: [Vec4; 6], // xyz normal pointing inside, w distance
+    pub count: usize,
+}
+
+impl Frustum
+{
+
+    pub fn from_matrix(view_proj: Mat4) -> Self
+    {
+        let m = view_proj.transpose(); // rows as columns
+        let (r0, r1, r2, r3) = (m.x_axis, m.y_axis, m.z_axis, m.w_axis);
+        let mut frustum = Self {planes: [Vec4::ZERO; 6], count: 0};
+        for p in [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r2, r3 - r2] {
+            let len = p.truncate().length();
+            if len > 1e-6 {
+                frustum.planes[frustum.count] = p / len;
+                frustum.count += 1;
+            }
+        }
+        frustum
+    }
+
+    pub fn from_camera(proj: &[[f32; 4]; 4], view: &[[f32; 4]; 4]) -> Self
+    {
+        Self::from_matrix(Mat4::from_cols_array_2d(proj) * Mat4::from_cols_array_2d(view))
+    }
+
+    // false only when the box is fully outside one plane
+    pub fn intersects_aabb(&self, min: Vec3, max: Vec3) -> bool
+    {
+        if min.cmpgt(max).any() {return false;} // empty
+        for p in &self.planes[.. self.count] {
+            // corner furthest along the plane normal
+            let n = p.truncate();
+            let corner = Vec3::select(n.cmpge(Vec3::ZERO), max, min);
+            if n.dot(corner) + p.w < 0.0 {return false;}
+        }
+        true
+    }
+
+}
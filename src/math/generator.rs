@@ -3,6 +3,7 @@ use noise::{NoiseFn, Worley};
 
 pub struct DistanceField
 {
+    pub seed: u32,
     lucifer: Worley,
 }
 
@@ -10,20 +11,40 @@ impl DistanceField
 {
 
     pub fn new() -> Self
+    {
+        Self::with_seed(Worley::DEFAULT_SEED)
+    }
+
+    pub fn with_seed(seed: u32) -> Self
     {
         Self
         {
-            lucifer: Default::default(),
+            seed,
+            lucifer: Worley::new(seed),
         }
     }
 
-    pub fn gen(&self, pos: DVec3) -> u8
+    // surface materials, a slope class of the field gradient (normal),
+    // flat enough to stand on is ground, anything steeper is rock
+    pub const MATERIAL_GROUND: u8 = 0;
+    pub const MATERIAL_ROCK: u8 = 1;
+
+    pub fn material(normal: DVec3) -> u8
+    {
+        if normal.y > 0.7 {Self::MATERIAL_GROUND} else {Self::MATERIAL_ROCK}
+    }
+
+    pub fn sample(&self, pos: DVec3) -> f64
     {
         // let df = df_sphere(pos, 5.0);
         // let df = df_plane(pos, dvec3(1.0, 1.0, 1.0), 1.0);
        // let df = df + 2.0 * pos.x.sin() * pos.y.sin() * pos.z.sin();
-        let df = self.lucifer.get([pos.x, pos.y, pos.z]);
-        Self::compress(df)
+        self.lucifer.get([pos.x, pos.y, pos.z])
+    }
+
+    pub fn gen(&self, pos: DVec3) -> u8
+    {
+        Self::compress(self.sample(pos))
     }
 
     pub fn compress_range(vals: Vec<f64>) -> Vec<u8> {
@@ -40,5 +61,9 @@ impl DistanceField
         std::cmp::min(std::cmp::max(0, d), 255) as u8
     }
 
+    pub fn decompress(v: u8) -> f64 {
+        (v as i32 - 128) as f64 / 64.0
+    }
+
 }
 
@@ -1,9 +1,12 @@
 use std::collections::{HashMap, HashSet, BinaryHeap};
+use std::collections::hash_map::{Entry, Iter, IterMut, Keys, Values, ValuesMut};
 use std::hash::{Hash, Hasher, BuildHasher};
 use std::ops::{Index, Range};
+use glam::*;
 use seahash::SeaHasher;
 use delegate::delegate;
 
+#[derive(Clone, Copy, Default)]
 pub struct SeaHash;
 
 impl BuildHasher for SeaHash {
@@ -20,26 +23,101 @@ impl BuildHasher for SeaHash {
 
 pub type SeaHashKey = [u8; 12];
 
+// little endian so keys are stable across platforms (persistence)
+#[inline]
+pub fn ivec3_to_key(v: IVec3) -> SeaHashKey
+{
+    let (x, y, z) = (
+        v.x.to_le_bytes(),
+        v.y.to_le_bytes(),
+        v.z.to_le_bytes(),
+    );
+    [
+        x[0], x[1], x[2], x[3],
+        y[0], y[1], y[2], y[3],
+        z[0], z[1], z[2], z[3],
+    ]
+}
+
+#[inline]
+pub fn key_to_ivec3(key: &SeaHashKey) -> IVec3
+{
+    ivec3(
+        i32::from_le_bytes(key[0..4].try_into().unwrap()),
+        i32::from_le_bytes(key[4..8].try_into().unwrap()),
+        i32::from_le_bytes(key[8..12].try_into().unwrap()),
+    )
+}
+
+// typed keys over the same encoding so chunk and voxel keys can't be mixed up
+// hash writes the raw bytes once (no length prefix as with arrays)
+macro_rules! typed_key {
+    ($name:ident) => {
+        #[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+        pub struct $name(SeaHashKey);
+
+        impl $name {
+            #[inline]
+            pub fn new(coord: IVec3) -> Self { Self(ivec3_to_key(coord)) }
+            #[inline]
+            pub fn coord(&self) -> IVec3 { key_to_ivec3(&self.0) }
+            #[inline]
+            pub fn to_bytes(&self) -> SeaHashKey { self.0 }
+            #[inline]
+            pub fn from_bytes(bytes: SeaHashKey) -> Self { Self(bytes) }
+        }
+
+        impl Hash for $name {
+            #[inline]
+            fn hash<H: Hasher>(&self, state: &mut H) { state.write(&self.0) }
+        }
+    };
+}
+
+// chunk space coordinate (unscaled by chunk size)
+typed_key!(ChunkKey);
+// absolute voxel coordinate (chunk origin + local coord)
+typed_key!(VoxelKey);
+
 //---- SeaHashMap
 
 pub struct SeaHashMap<K, V>(HashMap<K, V, SeaHash>);
 
+impl<K, V> Default for SeaHashMap<K, V> {
+    fn default() -> Self {
+        Self(HashMap::with_hasher(SeaHash))
+    }
+}
+
 impl<K, V> SeaHashMap<K, V>
     where K: Eq, K: Hash
 {
     pub fn new() -> SeaHashMap<K, V> {
-        Self {
-            0: HashMap::with_hasher(SeaHash)
-        }
+        Self(HashMap::with_hasher(SeaHash))
+    }
+
+    pub fn with_capacity(capacity: usize) -> SeaHashMap<K, V> {
+        Self(HashMap::with_capacity_and_hasher(capacity, SeaHash))
     }
 
     delegate! {
         to self.0 {
             pub fn index(&self, index: &K) -> &V;
             pub fn get(&self, k: &K) -> Option<&V>;
+            pub fn get_mut(&mut self, k: &K) -> Option<&mut V>;
             pub fn insert(&mut self, k: K, v: V) -> Option<V>;
+            pub fn remove(&mut self, k: &K) -> Option<V>;
+            pub fn entry(&mut self, k: K) -> Entry<'_, K, V>;
             pub fn contains_key(&self, k: &K) -> bool;
-            pub fn keys(&self) -> std::collections::hash_map::Keys<K, V>;
+            pub fn len(&self) -> usize;
+            pub fn is_empty(&self) -> bool;
+            pub fn clear(&mut self);
+            pub fn retain<F: FnMut(&K, &mut V) -> bool>(&mut self, f: F);
+            pub fn keys(&self) -> Keys<'_, K, V>;
+            pub fn values(&self) -> Values<'_, K, V>;
+            pub fn values_mut(&mut self) -> ValuesMut<'_, K, V>;
+            pub fn iter(&self) -> Iter<'_, K, V>;
+            pub fn iter_mut(&mut self) -> IterMut<'_, K, V>;
         }
     }
 }
@@ -56,7 +134,7 @@ impl<K, V> IntoIterator for SeaHashMap<K, V> {
 
 impl<'a, K, V> IntoIterator for &'a SeaHashMap<K, V> {
     type Item = (&'a K, &'a V);
-    type IntoIter = std::collections::hash_map::Iter<'a, K, V>;
+    type IntoIter = Iter<'a, K, V>;
     delegate! {
         to self.0 {
             #[call(iter)]
@@ -65,17 +143,36 @@ impl<'a, K, V> IntoIterator for &'a SeaHashMap<K, V> {
     }
 }
 
+impl<'a, K, V> IntoIterator for &'a mut SeaHashMap<K, V> {
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V>;
+    delegate! {
+        to self.0 {
+            #[call(iter_mut)]
+            fn into_iter(self) -> Self::IntoIter;
+        }
+    }
+}
+
 //---- SeaHashSet
 
 pub struct SeaHashSet<V>(HashSet<V, SeaHash>);
 
+impl<V> Default for SeaHashSet<V> {
+    fn default() -> Self {
+        Self(HashSet::with_hasher(SeaHash))
+    }
+}
+
 impl<V> SeaHashSet<V>
     where V: Eq, V: Hash
 {
     pub fn new() -> SeaHashSet<V> {
-        Self {
-            0: HashSet::with_hasher(SeaHash)
-        }
+        Self(HashSet::with_hasher(SeaHash))
+    }
+
+    pub fn with_capacity(capacity: usize) -> SeaHashSet<V> {
+        Self(HashSet::with_capacity_and_hasher(capacity, SeaHash))
     }
 
     delegate! {
@@ -84,8 +181,52 @@ impl<V> SeaHashSet<V>
             pub fn insert(&mut self, v: V) -> bool;
             pub fn clear(&mut self);
             pub fn remove(&mut self, v: &V) -> bool;
+            pub fn len(&self) -> usize;
+            pub fn is_empty(&self) -> bool;
+            pub fn retain<F: FnMut(&V) -> bool>(&mut self, f: F);
+            pub fn iter(&self) -> std::collections::hash_set::Iter<'_, V>;
         }
     }
 
 }
 
+impl<V> Extend<V> for SeaHashSet<V>
+    where V: Eq, V: Hash
+{
+    delegate! {
+        to self.0 {
+            fn extend<I: IntoIterator<Item = V>>(&mut self, iter: I);
+        }
+    }
+}
+
+impl<V> FromIterator<V> for SeaHashSet<V>
+    where V: Eq, V: Hash
+{
+    fn from_iter<I: IntoIterator<Item = V>>(iter: I) -> Self {
+        let mut s = Self::new();
+        s.extend(iter);
+        s
+    }
+}
+
+impl<V> IntoIterator for SeaHashSet<V> {
+    type Item = V;
+    type IntoIter = std::collections::hash_set::IntoIter<V>;
+    delegate! {
+        to self.0 {
+            fn into_iter(self) -> Self::IntoIter;
+        }
+    }
+}
+
+impl<'a, V> IntoIterator for &'a SeaHashSet<V> {
+    type Item = &'a V;
+    type IntoIter = std::collections::hash_set::Iter<'a, V>;
+    delegate! {
+        to self.0 {
+            #[call(iter)]
+            fn into_iter(self) -> Self::IntoIter;
+        }
+    }
+}
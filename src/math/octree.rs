@@ -119,7 +119,7 @@ pub struct SurfacePoint{
     pub normal: DVec3
 }
 
-pub type SurfaceMap = SeaHashMap<SeaHashKey, SurfacePoint>;
+pub type SurfaceMap = SeaHashMap<VoxelKey, SurfacePoint>;
 
 pub type SurfaceNode = OctreeNode<SurfacePoint>;
 pub type SurfaceOctree = Octree<SurfacePoint>;
@@ -0,0 +1,104 @@
+use glam::*;
+use super::{*,
+    direction::*,
+};
+
+// Transform -- position, orientation and scale
+// rotation is renormalized on every change so repeated updates stay exact
+// and reproducible (no matrix drift to correct for)
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform
+{
+    pub position: DVec3,
+    pub rotation: DQuat,
+    pub scale: DVec3,
+}
+
+impl Default for Transform
+{
+    fn default() -> Self { Self::IDENTITY }
+}
+
+impl Transform
+{
+    pub const IDENTITY: Self = Self {
+        position: DVec3::ZERO,
+        rotation: DQuat::IDENTITY,
+        scale: DVec3::ONE,
+    };
+
+    pub fn new(position: DVec3, rotation: DQuat) -> Self
+    {
+        Self {position, rotation, ..Self::IDENTITY}
+    }
+
+    pub fn from_position(position: DVec3) -> Self
+    {
+        Self {position, ..Self::IDENTITY}
+    }
+
+    // axes to world space
+    #[inline]
+    pub fn right(&self) -> DVec3 { self.rotation * DDirection::RIGHT }
+    #[inline]
+    pub fn up(&self) -> DVec3 { self.rotation * DDirection::UP }
+    #[inline]
+    pub fn forward(&self) -> DVec3 { self.rotation * DDirection::FORWARD }
+
+    #[inline]
+    pub fn transform_point(&self, point: DVec3) -> DVec3
+    {
+        self.position + self.rotation * (point * self.scale)
+    }
+
+    #[inline]
+    pub fn transform_vector(&self, vector: DVec3) -> DVec3
+    {
+        self.rotation * (vector * self.scale)
+    }
+
+    // local space rotation, angles as in mat_rotation
+    pub fn rotate_local(&mut self, angles: DVec3)
+    {
+        self.rotation = (self.rotation * quat_rotation(angles)).normalize();
+    }
+
+    // local space translation
+    pub fn translate_local(&mut self, trans: DVec3)
+    {
+        self.position += self.rotation * trans;
+    }
+
+    // child given relative to self, to world space
+    pub fn mul_transform(&self, child: &Transform) -> Transform
+    {
+        Transform {
+            position: self.transform_point(child.position),
+            rotation: (self.rotation * child.rotation).normalize(),
+            scale: self.scale * child.scale,
+        }
+    }
+
+    pub fn lerp(&self, other: &Transform, t: f64) -> Transform
+    {
+        Transform {
+            position: self.position.lerp(other.position, t),
+            rotation: self.rotation.slerp(other.rotation, t).normalize(),
+            scale: self.scale.lerp(other.scale, t),
+        }
+    }
+
+    pub fn to_mat4(&self) -> DMat4
+    {
+        DMat4::from_scale_rotation_translation(self.scale, self.rotation, self.position)
+    }
+
+    // view matrix when used as a camera (scale ignored)
+    pub fn inverse_mat4(&self) -> DMat4
+    {
+        let inv = self.rotation.conjugate();
+        DMat4::from_rotation_translation(inv, -(inv * self.position))
+    }
+
+}
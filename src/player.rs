@@ -1,23 +1,38 @@
-use std::collections::HashSet;
 use glam::*;
-use sdl2::keyboard::*;
 use crate::{
     math::{*,
         direction::*,
+        transform::Transform,
     },
     render::Mesh,
+    world::World,
+    input::{InputState, Action, Axis},
 };
 
+pub mod flight;
+pub mod collision;
+
+use flight::*;
+use collision::*;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PlayerMode
+{
+    Spectator, // free fly, no inertia
+    Flight,
+}
+
 pub struct Player
 {
     pub mesh: Mesh,
-    pub position: DVec3,
-    pub rotation: DMat4,
-    pub camera_pos: DVec3, // relative playerspace
-    pub camera_rot: DMat4,
+    pub transform: Transform,
+    pub camera: Transform, // relative playerspace
     pub player_speed: f64,
     pub camera_speed: f64,
     pub mouse_sensitivity: f64,
+    pub mode: PlayerMode,
+    pub flight: FlightModel,
+    pub collider: Collider,
 }
 
 impl Player
@@ -25,77 +40,108 @@ impl Player
 
 // get_input {{{
 
-    pub fn update(&mut self, elapsed_time: f64, keys: &HashSet<Keycode>, mouse: IVec2)
+    pub fn update(&mut self, elapsed_time: f64, input: &InputState)
+    {
+        if input.pressed(Action::ToggleMode) {
+            self.set_mode(match self.mode {
+                PlayerMode::Spectator => PlayerMode::Flight,
+                PlayerMode::Flight => PlayerMode::Spectator,
+            });
+        }
+
+        match self.mode {
+            PlayerMode::Spectator => self.update_spectator(elapsed_time, input),
+            PlayerMode::Flight => self.update_flight(elapsed_time, input),
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: PlayerMode)
+    {
+        if mode == self.mode {return;}
+        if mode == PlayerMode::Flight {
+            // launch along the view direction at spectator speed
+            self.flight.velocity = self.transform.forward() * self.player_speed;
+            self.flight.angular_velocity = DDirection::ZERO;
+        }
+        self.mode = mode;
+    }
+
+    pub fn update_spectator(&mut self, elapsed_time: f64, input: &InputState)
     {
         // camera space
-        let mut trans = dvec3(0.0, 0.0, 0.0);
-        let mut rot   = dvec3(0.0, 0.0, 0.0);
-        if keys.contains(&Keycode::W) { trans += DDirection::FORWARD * self.player_speed * elapsed_time; }
-        if keys.contains(&Keycode::S) { trans += DDirection::BACK * self.player_speed * elapsed_time; }
-        if keys.contains(&Keycode::A) { trans += DDirection::LEFT * self.player_speed * elapsed_time; }
-        if keys.contains(&Keycode::D) { trans += DDirection::RIGHT * self.player_speed * elapsed_time; }
-        if keys.contains(&Keycode::LShift) { trans += DDirection::DOWN * self.player_speed * elapsed_time; }
-        if keys.contains(&Keycode::Space) { trans  += DDirection::UP * self.player_speed * elapsed_time; }
-
-        //rotations subtract for left handed rotation
-        //keyboard camera control
-        // TODO: control switching
-        //if keys.contains(&Keycode::Up) { rot -= DDirection::RIGHT * self.camera_speed * elapsed_time; }
-        //if keys.contains(&Keycode::Down) { rot -= DDirection::LEFT * self.camera_speed * elapsed_time; }
-        //if keys.contains(&Keycode::Left) { rot -= DDirection::UP * self.camera_speed * elapsed_time; }
-        //if keys.contains(&Keycode::Right) { rot -= DDirection::DOWN * self.camera_speed * elapsed_time; }
-        // roll
-        if keys.contains(&Keycode::E) { rot -=  DDirection::BACK * self.camera_speed * elapsed_time; }
-        if keys.contains(&Keycode::Q) { rot -= DDirection::FORWARD * self.camera_speed * elapsed_time; }
-
-        //mouse camera control
-        if mouse.x != 0 { rot += DDirection::UP    * self.mouse_sensitivity * mouse.x as f64 * elapsed_time; }
-        if mouse.y != 0 { rot += DDirection::RIGHT * self.mouse_sensitivity * mouse.y as f64 * elapsed_time; }
-
-        self.position = self.position + (self.rotation * trans.extend(1.0)).truncate();
-        self.rotation = self.rotation * mat_rotation(rot);
-        let err = self.rotation.col(0).dot(self.rotation.col(1));
-        if err * err > 0.0
-        {
-            // taylor series estimation for error
-            let x_ort = self.rotation.col(0) - self.rotation.col(1) * (err / 2.0);
-            let y_ort = self.rotation.col(1) - self.rotation.col(2) * (err / 2.0);
-            let z_ort = x_ort.truncate().cross(y_ort.truncate()).extend(0.0);
-            self.rotation.x_axis = x_ort.normalize();
-            self.rotation.y_axis = y_ort.normalize();
-            self.rotation.z_axis = z_ort.normalize();
+        let trans = dvec3(
+            input.axis(Axis::MoveX),
+            input.axis(Axis::MoveY),
+            input.axis(Axis::MoveZ),
+        ) * self.player_speed * elapsed_time;
+
+        // look axes are mouse counts for this tick, roll is a -1..1 rate
+        let rot = dvec3(
+            input.axis(Axis::LookY) * self.mouse_sensitivity,
+            input.axis(Axis::LookX) * self.mouse_sensitivity,
+            input.axis(Axis::LookRoll) * self.camera_speed * elapsed_time,
+        );
+
+        self.transform.translate_local(trans);
+        self.transform.rotate_local(rot);
+    }
+
+    pub fn update_flight(&mut self, elapsed_time: f64, input: &InputState)
+    {
+        let controls = FlightControls {
+            pitch: input.axis(Axis::Pitch),
+            roll: input.axis(Axis::Roll),
+            yaw: input.axis(Axis::Yaw),
+            throttle: input.axis(Axis::Throttle),
+        };
+        self.flight.update(elapsed_time, &controls, &mut self.transform);
+    }
+
+    // resolve terrain contacts for the move since prev
+    pub fn collide(&mut self, world: &dyn World, prev: DVec3, elapsed_time: f64)
+    {
+        let mut velocity = match self.mode {
+            PlayerMode::Flight => self.flight.velocity,
+            PlayerMode::Spectator =>
+                if elapsed_time > 0.0 {(self.transform.position - prev) / elapsed_time}
+                else {DDirection::ZERO},
+        };
+        self.collider.resolve(world, prev, &mut self.transform.position, &mut velocity);
+        if self.mode == PlayerMode::Flight {
+            self.flight.velocity = velocity;
         }
     }
 
+    pub fn get_camera_transform(&self) -> Transform // to world space
+    {
+        self.transform.mul_transform(&self.camera)
+    }
+
     pub fn get_camera_pos(&self) -> DVec3 // to world space
     {
-        (
-             mat_translation(self.position) * self.get_camera_rot() * self.camera_pos.extend(1.0)
-        ).truncate()
-        // (mat_translation(self.position) * self.camera_pos.extend(1.0)).truncate()
+        self.get_camera_transform().position
     }
 
-    pub fn get_camera_rot(&self) -> DMat4 // to world space
+    pub fn get_camera_rot(&self) -> DQuat // to world space
     {
-         self.rotation * self.camera_rot
+        self.get_camera_transform().rotation
     }
 
     pub fn mat_view(&self) -> DMat4
     {
-        mat_quick_inv(mat_look_at(self.get_camera_pos(), self.get_camera_rot()))
+        self.get_camera_transform().inverse_mat4()
     }
 
 //}}}
 
     pub fn get_position(&self) -> DVec3
     {
-        self.mesh.position + self.position
+        self.mesh.position + self.transform.position
     }
 
-    pub fn get_rotation(&self) -> DMat4
+    pub fn get_rotation(&self) -> DQuat
     {
-         self.rotation * mat_rotation(self.mesh.rotation)
+        self.transform.rotation * quat_rotation(self.mesh.rotation)
     }
 
 }
-
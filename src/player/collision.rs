@@ -0,0 +1,139 @@
+use glam::*;
+use crate::{
+    math::{*,
+        direction::*,
+    },
+    world::World,
+};
+
+// Terrain collision -- sphere against the world field
+// contacts come from World::sphere_contact, the move is substepped
+// so the sphere never skips over a surface
+
+#[derive(Clone, Copy, Debug)]
+pub enum CollisionEvent {
+    Contact { position: DVec3, normal: DVec3, speed: f64 },
+    Crash { position: DVec3, normal: DVec3, speed: f64 },
+}
+
+pub struct Collider
+{
+    pub enabled: bool,
+    pub radius: f64,
+    pub crash_speed: f64,    // impact speed into the surface that counts as a crash
+    pub ground_margin: f64,  // distance below the sphere that still counts as ground
+    pub max_slope: f64,      // minimum normal.y considered ground
+    pub max_iterations: u32,
+    pub unstick_dist: f64,   // how far up to look for free space when starting inside
+    // state
+    pub grounded: bool,
+    pub ground_normal: DVec3,
+    pub events: Vec<CollisionEvent>,
+}
+
+impl Default for Collider
+{
+    fn default() -> Self
+    {
+        Self
+        {
+            enabled: true,
+            radius: 1.0,
+            crash_speed: 8.0,
+            ground_margin: 0.25,
+            max_slope: 0.7,
+            max_iterations: 4,
+            unstick_dist: 64.0,
+            grounded: false,
+            ground_normal: DDirection::UP,
+            events: vec![],
+        }
+    }
+}
+
+impl Collider
+{
+
+    pub fn new() -> Self
+    {
+        Self {..Default::default()}
+    }
+
+    // moves position from prev towards its current value, sliding along the terrain
+    // velocity loses its component into the surface
+    pub fn resolve(&mut self, world: &dyn World, prev: DVec3, position: &mut DVec3, velocity: &mut DVec3)
+    {
+        self.events.clear();
+        self.grounded = false;
+        if ! self.enabled {return;}
+
+        // started inside the terrain (spawn or teleport), lift out
+        if world.sample_value(prev) < 0.0 {
+            if let Some(t) = world.march(prev, DDirection::UP, self.unstick_dist) {
+                *position = prev + DDirection::UP * (t + self.radius);
+                *velocity = DDirection::ZERO;
+            }
+            return;
+        }
+
+        let delta = *position - prev;
+        let steps = ((delta.length() / (self.radius * 0.5)).ceil() as usize).max(1);
+        let mut step = delta / steps as f64;
+        let mut p = prev;
+        let mut impact: Option<(DVec3, DVec3, f64)> = None;
+        for _ in 0 .. steps {
+            // never let the center cross a surface
+            let len = step.length();
+            if len > 0.0 {
+                let dir = step / len;
+                if let Some(t) = world.march(p, dir, len) {
+                    step = dir * (t - self.radius * 0.5).max(0.0);
+                }
+            }
+            p += step;
+            for _ in 0 .. self.max_iterations {
+                let (_, normal, depth) = match world.sphere_contact(p, self.radius) {
+                    None => {break;}
+                    Some(c) => c,
+                };
+                p += normal * depth;
+                // slide the remaining move and velocity
+                let into = step.dot(normal);
+                if into < 0.0 { step -= normal * into; }
+                let speed = -velocity.dot(normal);
+                if speed > 0.0 {
+                    if impact.is_none_or(|(_, _, s)| speed > s) {
+                        impact = Some((p, normal, speed));
+                    }
+                    *velocity += normal * speed;
+                }
+            }
+        }
+        *position = p;
+
+        if let Some((position, normal, speed)) = impact {
+            self.events.push(
+                if speed > self.crash_speed {CollisionEvent::Crash{position, normal, speed}}
+                else {CollisionEvent::Contact{position, normal, speed}}
+            );
+        }
+
+        // ground contact
+        if let Some(t) = world.march(p, DDirection::DOWN, self.radius + self.ground_margin) {
+            let normal = world.sample_normal(p + DDirection::DOWN * t);
+            if normal.y >= self.max_slope {
+                self.grounded = true;
+                self.ground_normal = normal;
+            }
+        }
+    }
+
+    pub fn crashed(&self) -> Option<f64>
+    {
+        self.events.iter().find_map(|e| match e {
+            CollisionEvent::Crash{speed, ..} => Some(*speed),
+            _ => None,
+        })
+    }
+
+}
@@ -0,0 +1,174 @@
+use glam::*;
+use crate::math::{*,
+    direction::*,
+    transform::Transform,
+};
+
+// Flight model -- 6dof rigid body
+// body space follows the camera convention (x right, y up, z forward)
+// angular velocity follows mat_rotation (x nose down, y yaw right, z roll right)
+// units are scaled to the voxel world (1 unit ~ 1m), roughly a large rc model
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FlightControls {
+    pub pitch: f64,    // -1..1, positive nose up
+    pub roll: f64,     // -1..1, positive roll right
+    pub yaw: f64,      // -1..1, positive yaw right
+    pub throttle: f64, // -1..1, rate of throttle change
+}
+
+pub struct FlightModel
+{
+    // airframe
+    pub mass: f64,
+    pub inertia: DVec3,         // principal moments about body x, y, z
+    pub max_thrust: f64,
+    pub wing_area: f64,
+    pub lift_zero: f64,         // cl at zero angle of attack
+    pub lift_slope: f64,        // cl per radian
+    pub stall_angle: f64,       // radians
+    pub drag_zero: f64,         // parasitic cd
+    pub drag_induced: f64,      // cd per cl^2
+    pub side_force: f64,        // sideforce coefficient per radian of sideslip
+    pub stability: f64,         // weathervane moment per radian (pitch and yaw)
+    pub control_rates: DVec3,   // body rates at full deflection (pitch, yaw, roll)
+    pub control_response: f64,  // 1/s, how fast body rates follow the controls
+    pub control_speed: f64,     // airspeed for full control authority
+    pub throttle_rate: f64,     // throttle change per second at full input
+    // environment
+    pub air_density: f64,
+    pub gravity: f64,
+    // state
+    pub velocity: DVec3,         // world space
+    pub angular_velocity: DVec3, // body space
+    pub throttle: f64,           // 0..1
+    // integration
+    pub substeps: u32,           // per update, the game tick is fixed
+}
+
+impl Default for FlightModel
+{
+    fn default() -> Self
+    {
+        Self
+        {
+            mass: 10.0,
+            inertia: dvec3(1.5, 2.0, 1.0),
+            max_thrust: 40.0,
+            wing_area: 1.5,
+            lift_zero: 0.25,
+            lift_slope: 5.0,
+            stall_angle: 0.27,
+            drag_zero: 0.03,
+            drag_induced: 0.07,
+            side_force: 1.0,
+            stability: 0.4,
+            control_rates: dvec3(1.2, 0.6, 2.5),
+            control_response: 4.0,
+            control_speed: 12.0,
+            throttle_rate: 0.5,
+            air_density: 1.225,
+            gravity: 9.81,
+            velocity: DDirection::ZERO,
+            angular_velocity: DDirection::ZERO,
+            throttle: 0.5,
+            substeps: 2,
+        }
+    }
+}
+
+impl FlightModel
+{
+
+    pub fn new() -> Self
+    {
+        Self {..Default::default()}
+    }
+
+    // elapsed_time is one game tick, split into equal substeps
+    pub fn update(&mut self, elapsed_time: f64, controls: &FlightControls, transform: &mut Transform)
+    {
+        let n = self.substeps.max(1);
+        let dt = elapsed_time / n as f64;
+        for _ in 0 .. n {
+            self.step(dt, controls, transform);
+        }
+    }
+
+    // angle of attack and sideslip from body space velocity
+    #[inline]
+    pub fn aero_angles(v_body: DVec3) -> (f64, f64)
+    {
+        let alpha = (-v_body.y).atan2(v_body.z);
+        let beta = v_body.x.atan2(v_body.z);
+        (alpha, beta)
+    }
+
+    pub fn lift_coefficient(&self, alpha: f64) -> f64
+    {
+        if alpha.abs() <= self.stall_angle {
+            self.lift_zero + self.lift_slope * alpha
+        }
+        else {
+            // linear falloff past stall, gone at twice the stall angle
+            let peak = self.lift_zero + self.lift_slope * self.stall_angle * dsign(alpha);
+            let falloff = 1.0 - (alpha.abs() - self.stall_angle) / self.stall_angle;
+            peak * falloff.max(0.0)
+        }
+    }
+
+    pub fn airspeed(&self) -> f64 { self.velocity.length() }
+
+    pub fn step(&mut self, dt: f64, controls: &FlightControls, transform: &mut Transform)
+    {
+        self.throttle = (self.throttle + controls.throttle * self.throttle_rate * dt).clamp(0.0, 1.0);
+
+        let right   = transform.right();
+        let up      = transform.up();
+        let forward = transform.forward();
+
+        // forces (world space)
+        let mut force = DDirection::DOWN * self.gravity * self.mass;
+        force += forward * self.throttle * self.max_thrust;
+
+        let speed = self.velocity.length();
+        let mut effectiveness = 0.0;
+        let mut aero_moment = DDirection::ZERO;
+        if speed > 1e-6 {
+            let v_dir = self.velocity / speed;
+            let v_body = dvec3(self.velocity.dot(right), self.velocity.dot(up), self.velocity.dot(forward));
+            let (alpha, beta) = Self::aero_angles(v_body);
+            let q = 0.5 * self.air_density * speed * speed;
+            let qs = q * self.wing_area;
+
+            let cl = self.lift_coefficient(alpha);
+            let lift_dir = up - v_dir * up.dot(v_dir);
+            if lift_dir.length_squared() > 1e-12 {
+                force += lift_dir.normalize() * qs * cl;
+            }
+            let cd = self.drag_zero + self.drag_induced * cl * cl;
+            force -= v_dir * qs * cd;
+            force -= right * qs * self.side_force * beta;
+
+            // nose follows the flight path (positive alpha pitches nose down)
+            aero_moment = dvec3(alpha, beta, 0.0) * qs * self.stability;
+            let ref_q = 0.5 * self.air_density * self.control_speed * self.control_speed;
+            effectiveness = (q / ref_q).min(1.0);
+        }
+
+        // control surfaces command body rates, scaled by dynamic pressure
+        // pitch input is nose up, which is negative about body x
+        let target_rate = dvec3(-controls.pitch, controls.yaw, controls.roll)
+            .clamp(dvec3(-1.0, -1.0, -1.0), dvec3(1.0, 1.0, 1.0))
+            * self.control_rates * effectiveness;
+        let control_moment = self.inertia * (target_rate - self.angular_velocity) * self.control_response * effectiveness;
+        let angular_accel = (control_moment + aero_moment) / self.inertia;
+
+        // semi-implicit euler
+        self.velocity += force / self.mass * dt;
+        transform.position += self.velocity * dt;
+        self.angular_velocity += angular_accel * dt;
+        transform.rotate_local(self.angular_velocity * dt);
+    }
+
+}
@@ -1,31 +1,63 @@
-use std::collections::BinaryHeap;
+use std::time::{Duration, Instant};
+#[cfg(feature = "window")]
 use wgpu::*;
 use glam::*;
 use crate::{
     math::{*,
         hasher::*,
         octree::*,
+        frustum::Frustum,
+        generator::DistanceField,
     },
     game::Game,
-    render::globals::*,
+    render::{
+        globals::*,
+        alloc::*,
+    },
 };
 
 pub mod globals;
+pub mod alloc;
+#[cfg(feature = "window")]
 pub mod terrain;
+#[cfg(feature = "window")]
 pub mod sdf;
+#[cfg(feature = "window")]
+pub mod hud;
+#[cfg(feature = "window")]
+pub mod cull;
 
 pub struct GameData<'a>
 {
-    pub general_triangles: Vec<Triangle>,
-    pub visible_meshes: Vec<(SeaHashKey, &'a IndexedMesh)>,
-    pub updated_mesh_keys: &'a SeaHashSet<SeaHashKey>,
+    pub general_triangles: Vec<Triangle>, // model space, see objects
+    pub objects: Vec<ObjectDraw>,
+    pub visible_meshes: Vec<(ChunkKey, &'a IndexedMesh)>,
+    pub updated_mesh_keys: &'a SeaHashSet<ChunkKey>,
+    pub chunk_extent: f32, // world units, for unpacking terrain vertices
     pub camera: CameraUniform,
     pub light: LightUniform,
 }
 
+#[cfg(feature = "window")]
 pub trait Pass {
-    fn update(&mut self, queue: &Queue, gamedata: &GameData) {}
+    // uploads can be staged into encoder, submitted before recall
+    fn update(&mut self, device: &Device, queue: &Queue, encoder: &mut CommandEncoder, gamedata: &GameData) {}
+    fn recall(&mut self) {}
+    // surface size changed, never zero
+    fn resize(&mut self, device: &Device, config: &SurfaceConfiguration) {}
     fn draw(&mut self, view: &TextureView, encoder: &mut CommandEncoder) -> Result<(), SurfaceError>;
+    // cpu time of the last chunk buffer upload
+    fn upload_time(&self) -> Duration {Duration::ZERO}
+    fn buffer_stats(&self) -> Option<BufferStats> {None}
+}
+
+// a run of general triangles drawn with one model matrix
+#[derive(Clone, Copy, Debug)]
+pub struct ObjectDraw
+{
+    pub first: u32, // triangles
+    pub count: u32,
+    pub uniform: ObjectUniform,
 }
 
 #[derive(Clone, Copy)]
@@ -63,6 +95,64 @@ impl Vertex {
 
 //}}}
 
+//{{{ TerrainVertex
+
+// packed chunk mesh vertex, 16 bytes against Vertex's 48
+// position quantized over the chunk grown by half a chunk on each side,
+// the chunk coordinate is kept mod 256 and unwrapped around the camera's chunk
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct TerrainVertex {
+    pub position: [u16; 4], // xyz quantized, w material
+    pub normal: [i16; 2],   // octahedral, snorm
+    pub chunk: [u8; 4],     // xyz low bits of the chunk coordinate
+}
+
+impl TerrainVertex {
+    pub const fn size_of() -> usize { std::mem::size_of::<Self>() }
+
+    // local range in chunk extents, matched in terrain.wgsl
+    pub const QUANT_MIN: f32 = -0.5;
+    pub const QUANT_RANGE: f32 = 2.0;
+
+    pub fn pack(chunk: IVec3, extent: f32, position: Vec3, normal: Vec3, material: u8) -> Self {
+        let local = position / extent - chunk.as_vec3();
+        let q = ((local - Self::QUANT_MIN) / Self::QUANT_RANGE * 65535.0).round().clamp(Vec3::ZERO, Vec3::splat(65535.0));
+        let n = (oct_encode(normal) * 32767.0).round();
+        Self {
+            position: [q.x as u16, q.y as u16, q.z as u16, material as u16],
+            normal: [n.x as i16, n.y as i16],
+            chunk: [chunk.x as u8, chunk.y as u8, chunk.z as u8, 0],
+        }
+    }
+
+    // back to a full vertex, the mesh knows the whole chunk coordinate
+    pub fn unpack(&self, chunk: IVec3, extent: f32) -> Vertex {
+        let q = uvec3(self.position[0] as u32, self.position[1] as u32, self.position[2] as u32).as_vec3();
+        let local = q / 65535.0 * Self::QUANT_RANGE + Self::QUANT_MIN;
+        let position = (chunk.as_vec3() + local) * extent;
+        let normal = oct_decode(vec2(self.normal[0] as f32, self.normal[1] as f32) / 32767.0);
+        Vertex {
+            position: position.extend(1.0).to_array(),
+            normal: normal.extend(0.0).to_array(),
+            color: Self::material_color(self.position[3] as u8),
+        }
+    }
+
+    // palette indexed by DistanceField::MATERIAL_*, matched in terrain.wgsl,
+    // unknown materials use the first
+    pub const PALETTE: [[f32; 4]; 2] = [
+        [0.45, 0.55, 0.3, 1.0], // ground
+        [0.5, 0.48, 0.45, 1.0], // rock
+    ];
+
+    pub fn material_color(material: u8) -> [f32; 4] {
+        *Self::PALETTE.get(material as usize).unwrap_or(&Self::PALETTE[0])
+    }
+}
+
+//}}}
+
 // Triangle --------------------------{{{
 
 #[derive(Clone, Copy)]
@@ -311,6 +401,68 @@ impl Mesh
         self.has_texture = true;
     }
 
+    // low poly stand in until there is a model to load, nose along +z
+    pub fn placeholder_aircraft() -> Self
+    {
+        let body = Pixel{r: 200, g: 200, b: 210, a: 0xFF};
+        let trim = Pixel{r: 200, g: 60, b: 40, a: 0xFF};
+        let mut tris = vec![];
+        // winding picked so the normal faces away from inside
+        let mut tri = |a: DVec3, b: DVec3, c: DVec3, color: Pixel, inside: DVec3| {
+            let mut t = Triangle{color, ..Triangle::from_dvec3(a, b, c)};
+            t.calc_normal(1.0);
+            if t.normal.dot((a + b + c) / 3.0 - inside) < 0.0 {
+                t = Triangle{color, ..Triangle::from_dvec3(a, c, b)};
+            }
+            tris.push(t);
+        };
+
+        // fuselage, nose and tail cones off a diamond section
+        let nose = dvec3(0.0, 0.0, 2.5);
+        let tail = dvec3(0.0, 0.2, -2.2);
+        let ring = [
+            dvec3(0.0, 0.45, 0.5),
+            dvec3(0.35, 0.0, 0.5),
+            dvec3(0.0, -0.3, 0.5),
+            dvec3(-0.35, 0.0, 0.5),
+        ];
+        for i in 0 .. 4 {
+            let (a, b) = (ring[i], ring[(i + 1) % 4]);
+            tri(nose, a, b, body, dvec3(0.0, 0.0, 1.0));
+            tri(a, b, tail, body, dvec3(0.0, 0.05, -0.5));
+        }
+
+        // flat surfaces, mirrored left and right, facing up
+        let below = dvec3(0.0, -100.0, 0.0);
+        let mut quad = |q: [DVec3; 4], color: Pixel, inside: DVec3| {
+            for side in [1.0, -1.0] {
+                let q = q.map(|p| dvec3(p.x * side, p.y, p.z));
+                tri(q[0], q[1], q[2], color, inside);
+                tri(q[0], q[2], q[3], color, inside);
+            }
+        };
+        // wings
+        quad([
+            dvec3(0.3, 0.0, 0.9),
+            dvec3(2.6, 0.1, -0.1),
+            dvec3(2.6, 0.1, -0.6),
+            dvec3(0.3, 0.0, -0.5),
+        ], body, below);
+        // tailplane
+        quad([
+            dvec3(0.1, 0.15, -1.5),
+            dvec3(0.9, 0.15, -1.9),
+            dvec3(0.9, 0.15, -2.1),
+            dvec3(0.1, 0.15, -2.0),
+        ], body, below);
+        // fin
+        let inside = dvec3(-1.0, 0.0, 0.0);
+        tri(dvec3(0.0, 0.2, -1.4), dvec3(0.0, 1.0, -2.0), dvec3(0.0, 1.0, -2.3), trim, inside);
+        tri(dvec3(0.0, 0.2, -1.4), dvec3(0.0, 1.0, -2.3), dvec3(0.0, 0.2, -2.2), trim, inside);
+
+        Self {tris, ..Default::default()}
+    }
+
     // unlike general meshes, only preproc once bc attr will not change
     // skip rotation and translation
     pub fn preprocess_chunk_mesh(&mut self) {
@@ -322,59 +474,58 @@ impl Mesh
         }
     }
 
-    pub fn preprocess_mesh(&self, tris_to_raster: &mut Vec<Triangle>)
+    // model space with normals, the model matrix is applied on the gpu
+    pub fn model_triangles(&self, tris: &mut Vec<Triangle>)
     {
-        let position = self.position;
-        let rotation = mat_rotation(self.rotation);
         for tri in self.tris.iter()
         {
-            // modelspace
             let mut tri = *tri; // copy
-
-            // model rotation and translation to worldspace
-            tri.transform(rotation);
-            tri.translate(position);
-
-            // get tri normal
             tri.calc_normal(self.normal_dir);
-            tris_to_raster.push(tri);
+            tris.push(tri);
         }
     }
 
+    // local offset and rotation
+    pub fn model_mat4(&self) -> DMat4
+    {
+        mat_translation(self.position) * mat_rotation(self.rotation)
+    }
+
 }
 
 //}}}
 
 //{{{ IndexedMesh
 
+// heap backed, sized to the chunk's surface
 pub struct IndexedMesh {
-    // pub key: SeaHashKey,
-    pub inds:  [u32; Self::MAX_INDEX as usize],
-    pub verts: [Vertex; Self::MAX_VERTS as usize],
-    pub vert_index: SeaHashMap<SeaHashKey, usize>,
-    pub next_ind: usize,
-    pub next_vert: usize,
+    // pub key: ChunkKey,
+    pub chunk: IVec3,  // packing origin
+    pub extent: f32,   // chunk size in world units
+    pub inds:  Vec<u32>,
+    pub verts: Vec<TerrainVertex>,
+    pub vert_index: SeaHashMap<VoxelKey, usize>,
+    pub bounds_min: Vec3, // of the vertices, empty while inverted
+    pub bounds_max: Vec3,
 }
 
 impl Default for IndexedMesh {
     fn default() -> Self {
         Self {
-            // key: World::coord2key(IVec3::ZERO),
-            inds: [0; Self::MAX_INDEX as usize],
-            verts: [Default::default(); Self::MAX_VERTS as usize],
+            // key: ChunkKey::new(IVec3::ZERO),
+            chunk: IVec3::ZERO,
+            extent: 1.0,
+            inds: vec![],
+            verts: vec![],
             vert_index: SeaHashMap::new(),
-            next_ind: 0,
-            next_vert: 0,
+            bounds_min: Vec3::splat(f32::INFINITY),
+            bounds_max: Vec3::splat(f32::NEG_INFINITY),
         }
     }
 
 }
 
 impl IndexedMesh {
-    const MAX_INDEX: u64 = Mesh::MAX_VERTS; // 3072
-    const MAX_VERTS: u64 = 8 * 8 * 8;   //  512
-    const MAX_INDEX_MEM: usize = Self::MAX_INDEX as usize * 4; // u32
-    const MAX_VERTS_MEM: usize = Self::MAX_VERTS as usize * Vertex::size_of();
 
     pub fn new() -> Self {
         Self {
@@ -383,77 +534,87 @@ impl IndexedMesh {
         }
     }
 
+    pub fn for_chunk(chunk: IVec3, extent: f32) -> Self {
+        Self {
+            chunk,
+            extent,
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    pub fn index_count(&self) -> usize { self.inds.len() }
+    #[inline]
+    pub fn vertex_count(&self) -> usize { self.verts.len() }
+
+    // bytes held by the mesh data, lookup map not counted
+    pub fn memory_usage(&self) -> usize {
+        self.inds.capacity() * 4 + self.verts.capacity() * TerrainVertex::size_of()
+    }
+
     pub fn add_positions(&mut self, verts: &[(IVec3, IVec3, SurfacePoint)]) {
-        for i in (0 .. verts.len()).step_by(3) {
-            if self.next_ind + 2 >= Self::MAX_INDEX as usize {return;}
+        for i in (0 .. verts.len() / 3 * 3).step_by(3) {
             for j in 0..3 {
                 // chunk coord, vox coord, position, normal
                 let (c, v, sfp) = verts[i + j];
-                let (ckey, vkey) = (coord2key(c), coord2key(c + v));
-                let vi = self.vert_index.get(&vkey);
-                match vi {
+                let vkey = VoxelKey::new(c + v);
+                let vi = match self.vert_index.get(&vkey) {
+                    Some(vi) => *vi,
                     None => {
-                        if self.next_vert >= Self::MAX_VERTS as usize {return;}
-                        self.vert_index.insert(vkey, self.next_vert);
-                        self.inds[self.next_ind] = self.next_vert as u32;
-                        self.verts[self.next_vert] = Vertex{
-                            position: [sfp.position.x as f32, sfp.position.y as f32, sfp.position.z as f32, 1.0],
-                            normal: [sfp.normal.x as f32, sfp.normal.y as f32, sfp.normal.z as f32, 0.0],
-                            ..Default::default()
-                        };
-                        self.next_vert += 1;
+                        let vi = self.verts.len();
+                        self.vert_index.insert(vkey, vi);
+                        let p = sfp.position.as_vec3();
+                        self.verts.push(TerrainVertex::pack(self.chunk, self.extent, p, sfp.normal.as_vec3(), DistanceField::material(sfp.normal)));
+                        self.bounds_min = self.bounds_min.min(p);
+                        self.bounds_max = self.bounds_max.max(p);
+                        vi
                     }
-                    Some(vi) => {
-                        self.inds[self.next_ind] = *vi as u32;
-                    }
-                }
-                self.next_ind += 1;
+                };
+                self.inds.push(vi as u32);
             }
         }
     }
 
-    pub fn calc_plain_normals(&self, ret: &mut [Vertex; Self::MAX_INDEX as usize]) {
-        for i in (0 .. self.next_ind).step_by(3) {
+    // drop spare capacity once meshing is done
+    pub fn shrink_to_fit(&mut self) {
+        self.inds.shrink_to_fit();
+        self.verts.shrink_to_fit();
+    }
+
+    pub fn calc_plain_normals(ret: &mut [Vertex]) {
+        for tri in ret.chunks_exact_mut(3) {
             let (v0, v1, v2) = (
-                Vec4::from_array(ret[i].position).truncate(),
-                Vec4::from_array(ret[i+1].position).truncate(),
-                Vec4::from_array(ret[i+2].position).truncate()
+                Vec4::from_array(tri[0].position).truncate(),
+                Vec4::from_array(tri[1].position).truncate(),
+                Vec4::from_array(tri[2].position).truncate()
             );
             let normal = (v1 - v0).cross(v2 - v0).normalize().extend(0.0).to_array();
-            ret[i].normal = normal;
-            ret[i+1].normal = normal;
-            ret[i+2].normal = normal;
+            for v in tri {
+                v.normal = normal;
+            }
         }
     }
 
     // indices are relative, need to adjust based on vertex buffer offset given from pool
-    // will need to copy
-    pub fn index_array(&self, offset: u32) -> [u8; Self::MAX_INDEX_MEM] {
-        let mut ret = [0; Self::MAX_INDEX as usize];
-        let mut i = 0;
-        for ind in self.inds {
-            ret[i] = offset + ind;
-            i += 1;
+    pub fn index_array(&self, offset: u32) -> Vec<u8> {
+        let mut ret = Vec::with_capacity(self.inds.len() * 4);
+        for ind in self.inds.iter() {
+            ret.extend_from_slice(&(offset + ind).to_ne_bytes());
         }
-        let arr = unsafe { std::mem::transmute::<[u32; Self::MAX_INDEX as usize], [u8; Self::MAX_INDEX_MEM]>(ret) };
-        arr
+        ret
     }
 
-    pub fn vertex_array(&self) -> &[u8; Self::MAX_VERTS_MEM] {
-        let arr = unsafe { std::mem::transmute::<&[Vertex; Self::MAX_VERTS as usize], &[u8; Self::MAX_VERTS_MEM]>(&self.verts) };
-        arr
+    pub fn vertex_array(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.verts.as_ptr() as *const u8, self.verts.len() * TerrainVertex::size_of()) }
     }
 
-    pub const MAX_PLAIN_MEM : usize = Self::MAX_INDEX as usize * Vertex::size_of();
-
-    pub fn plain_vertex_array(&self) -> [u8; Self::MAX_PLAIN_MEM] {
-        let mut ret = [Default::default(); Self::MAX_INDEX as usize];
-        for i in 0 .. self.inds.len() {
-            ret[i] = self.verts[self.inds[i] as usize];
-        }
-        self.calc_plain_normals(&mut ret);
-        let arr = unsafe { std::mem::transmute::<[Vertex; Self::MAX_INDEX as usize], [u8; Self::MAX_PLAIN_MEM]>(ret) };
-        arr
+    pub fn plain_vertex_array(&self) -> Vec<u8> {
+        let mut ret: Vec<Vertex> = self.inds.iter()
+            .map(|i| self.verts[*i as usize].unpack(self.chunk, self.extent))
+            .collect();
+        Self::calc_plain_normals(&mut ret);
+        let bytes = unsafe { std::slice::from_raw_parts(ret.as_ptr() as *const u8, ret.len() * Vertex::size_of()) };
+        bytes.to_vec()
     }
 
 }
@@ -461,12 +622,14 @@ impl IndexedMesh {
 
 //{{{ SimpleTexture
 
+#[cfg(feature = "window")]
 pub struct SimpleTexture {
     pub texture: Texture,
     pub view: TextureView,
     pub sampler: Sampler,
 }
 
+#[cfg(feature = "window")]
 impl SimpleTexture {
     pub const DEPTH_FORMAT: TextureFormat = TextureFormat::Depth32Float;
 
@@ -511,6 +674,59 @@ impl SimpleTexture {
             sampler,
         }
     }
+
+    // single channel, one byte per texel, nearest sampling
+    pub fn create_r8_texture(device: &Device, queue: &Queue, width: u32, height: u32, data: &[u8], label: &str) -> Self {
+        let size = Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::R8Unorm,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            data,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(width),
+                rows_per_image: std::num::NonZeroU32::new(height),
+            },
+            size,
+        );
+
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let sampler = device.create_sampler(
+            &SamplerDescriptor {
+                address_mode_u: AddressMode::ClampToEdge,
+                address_mode_v: AddressMode::ClampToEdge,
+                address_mode_w: AddressMode::ClampToEdge,
+                mag_filter: FilterMode::Nearest,
+                min_filter: FilterMode::Nearest,
+                mipmap_filter: FilterMode::Nearest,
+                ..Default::default()
+            }
+        );
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
 }
 
 //}}}
@@ -577,91 +793,327 @@ impl Light
 
 // }}}
 
-//{{{ BucketPool
+//{{{ MeshPool
 
 // virtual bufferpool
-// does not actually track memory/buffers, only offsets and buffer number
+// does not actually track memory/buffers, only spans of vertices and indices per buffer
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct BucketCoord {
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct MeshAlloc {
     //order dependent
-    buffer: u16,
-    offset: u16,
+    pub buffer: u16,
+    pub vertex: Span,
+    pub index: Span,
 }
 
-type MinBinaryHeap<T> = BinaryHeap<std::cmp::Reverse<T>>;
-
-pub struct BucketPool {
+pub struct MeshPool {
     // total number of pools (buffers)
     pub dims: u16,
-    // total number of buckets
-    pub size: u16,
-    // tracks current number of dims loaded into pool (size)
+    // tracks current number of dims loaded into pool
     pub cur_dim: u16,
-    pub pool: MinBinaryHeap<BucketCoord>,
-    pub reserved: SeaHashMap<SeaHashKey, BucketCoord>,
+    // allocations per buffer, bounded by its draw list
+    pub max_allocs: usize,
+    pub allocs: Vec<usize>,
+    pub vertices: Vec<SpanAllocator>,
+    pub indices: Vec<SpanAllocator>,
+    pub reserved: SeaHashMap<ChunkKey, MeshAlloc>,
 }
 
-impl BucketPool {
+impl MeshPool {
 
     #[inline]
-    pub fn len(&self) -> usize {self.reserved.keys().len()}
+    pub fn len(&self) -> usize {self.reserved.len()}
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {self.reserved.is_empty()}
 
     #[inline]
     pub fn is_expandable(&self) -> bool {self.dims > self.cur_dim}
 
-    pub fn new(dims: u16, size: u16) -> Self {
-        let mut s = Self {
+    pub fn new(dims: u16, vertex_capacity: u32, index_capacity: u32, max_allocs: usize) -> Self {
+        Self {
             dims,
-            size,
-            cur_dim: 0,
-            pool: MinBinaryHeap::with_capacity((dims * size) as usize),
+            cur_dim: 1,
+            max_allocs,
+            allocs: vec![0; dims as usize],
+            vertices: vec![SpanAllocator::new(vertex_capacity); dims as usize],
+            indices: vec![SpanAllocator::new(index_capacity); dims as usize],
             reserved: SeaHashMap::new(),
-        };
-        s.expand();
-        s
+        }
     }
 
     pub fn expand(&mut self) -> bool {
         if ! self.is_expandable() {return false;}
-        self.pool.append(
-            &mut (0..self.size)
-                .map(|x| std::cmp::Reverse(BucketCoord{buffer: self.cur_dim, offset: x}))
-                .collect::<MinBinaryHeap<BucketCoord>>()
-        );
         self.cur_dim += 1;
         true
     }
 
-    pub fn reserve(&mut self, key: &SeaHashKey, force: &SeaHashSet<SeaHashKey>) -> Option<BucketCoord> {
-        if self.reserved.contains_key(key) {
-            if ! force.contains(key) {None}
-            else {Some(*self.reserved.get(key).unwrap())}
+    // fewer buffers than planned, those past dims must be empty
+    pub fn limit(&mut self, dims: u16) {
+        self.dims = self.dims.min(dims);
+        self.cur_dim = self.cur_dim.min(self.dims);
+        self.allocs.truncate(self.dims as usize);
+        self.vertices.truncate(self.dims as usize);
+        self.indices.truncate(self.dims as usize);
+    }
+
+    fn alloc_in(&mut self, buffer: usize, verts: u32, inds: u32) -> Option<MeshAlloc> {
+        if self.allocs[buffer] >= self.max_allocs {return None;}
+        let vertex = self.vertices[buffer].alloc(verts)?;
+        match self.indices[buffer].alloc(inds) {
+            Some(index) => {
+                self.allocs[buffer] += 1;
+                Some(MeshAlloc {buffer: buffer as u16, vertex, index})
+            }
+            None => {
+                self.vertices[buffer].free(vertex);
+                None
+            }
         }
-        else {
-            if self.pool.is_empty() && ! self.expand() {None}
-            else {
-                let i = self.pool.pop().unwrap().0;
-                self.reserved.insert(*key, i);
-                Some(i)
+    }
+
+    // first buffer with room, expanding when none has
+    // empty meshes still take a unit so every reservation is distinct
+    pub fn reserve(&mut self, key: &ChunkKey, verts: usize, inds: usize) -> Option<MeshAlloc> {
+        let (verts, inds) = (verts.max(1) as u32, inds.max(1) as u32);
+        let mut buffer = 0;
+        loop {
+            if buffer >= self.cur_dim as usize && ! self.expand() {return None;}
+            if let Some(a) = self.alloc_in(buffer, verts, inds) {
+                self.reserved.insert(*key, a);
+                return Some(a);
             }
+            buffer += 1;
         }
     }
 
-    // return removed
-    pub fn keep_reserved(&mut self, keep: &Vec<(SeaHashKey, &IndexedMesh)>) -> Vec<BucketCoord> {
-        let mut keep_reserved : SeaHashMap<SeaHashKey, BucketCoord> = SeaHashMap::new();
-        let mut removed = vec![];
-        for (k, v) in &self.reserved {
-            if ! keep.iter().any(|x| x.0 == *k) {
-                self.pool.push(std::cmp::Reverse(*v));
-                removed.push(*v);
-            } else {
-                keep_reserved.insert(*k, *v);
+    pub fn release(&mut self, key: &ChunkKey) -> Option<MeshAlloc> {
+        let a = self.reserved.remove(key)?;
+        let b = a.buffer as usize;
+        self.vertices[b].free(a.vertex);
+        self.indices[b].free(a.index);
+        self.allocs[b] -= 1;
+        Some(a)
+    }
+
+    // true when a reservation already has the mesh's exact size
+    pub fn fits(a: &MeshAlloc, verts: usize, inds: usize) -> bool {
+        a.vertex.len == verts.max(1) as u32 && a.index.len == inds.max(1) as u32
+    }
+
+    // (bytes allocated, capacity) per buffer, unexpanded buffers included
+    pub fn occupancy(&self) -> Vec<(usize, usize)> {
+        let vsize = TerrainVertex::size_of();
+        self.vertices.iter().zip(self.indices.iter())
+            .map(|(v, i)| (
+                v.used as usize * vsize + i.used as usize * 4,
+                v.capacity as usize * vsize + i.capacity as usize * 4,
+            ))
+            .collect()
+    }
+
+    pub fn is_compact(&self) -> bool {
+        (0 .. self.cur_dim as usize).all(|b| self.vertices[b].is_compact() && self.indices[b].is_compact())
+    }
+
+    // free spans left after merging, 1 per buffer when compact
+    pub fn free_spans(&self) -> usize {
+        (0 .. self.cur_dim as usize)
+            .map(|b| self.vertices[b].free_spans() + self.indices[b].free_spans())
+            .sum()
+    }
+
+    // bytes a reservation takes in its buffers
+    pub fn alloc_size(a: &MeshAlloc) -> usize {
+        a.vertex.len as usize * TerrainVertex::size_of() + a.index.len as usize * 4
+    }
+
+    // slide allocations down into the holes before them, lowest first
+    // at most max_moves moves, stopping once they add up to bytes, only keys the caller can rewrite
+    // returns (key, from, to), the data needs writing at the new spans
+    pub fn compact(&mut self, max_moves: usize, bytes: usize, movable: impl Fn(&ChunkKey, &MeshAlloc) -> bool)
+        -> Vec<(ChunkKey, MeshAlloc, MeshAlloc)>
+    {
+        let mut moves = vec![];
+        let mut left = bytes;
+        for b in 0 .. self.cur_dim as usize {
+            if moves.len() >= max_moves || left == 0 {break;}
+            if self.vertices[b].is_compact() && self.indices[b].is_compact() {continue;}
+            let (vfree, ifree) = (self.vertices[b].first_free(), self.indices[b].first_free());
+            let mut in_buffer: Vec<(ChunkKey, MeshAlloc)> = self.reserved.iter()
+                .filter(|(_, a)| a.buffer as usize == b && (a.vertex.offset > vfree || a.index.offset > ifree))
+                .filter(|(k, a)| movable(k, a))
+                .map(|(k, a)| (*k, *a))
+                .collect();
+            in_buffer.sort_by_key(|(_, a)| a.vertex.offset.min(a.index.offset));
+            for (key, from) in in_buffer {
+                if moves.len() >= max_moves || left == 0 {break;}
+                // freed first so a span can slide over its own old place
+                let (vertices, indices) = (&mut self.vertices[b], &mut self.indices[b]);
+                vertices.free(from.vertex);
+                indices.free(from.index);
+                let to = MeshAlloc {
+                    buffer: from.buffer,
+                    vertex: vertices.alloc_below(from.vertex.len, from.vertex.offset)
+                        .or_else(|| vertices.alloc_at(from.vertex)).unwrap(),
+                    index: indices.alloc_below(from.index.len, from.index.offset)
+                        .or_else(|| indices.alloc_at(from.index)).unwrap(),
+                };
+                if to != from {
+                    self.reserved.insert(key, to);
+                    moves.push((key, from, to));
+                    left = left.saturating_sub(Self::alloc_size(&to));
+                }
             }
         }
-        self.reserved = keep_reserved;
-        removed
+        moves
+    }
+
+    // return removed
+    pub fn keep_reserved(&mut self, keep: &SeaHashSet<ChunkKey>) -> Vec<MeshAlloc> {
+        let gone: Vec<ChunkKey> = self.reserved.keys()
+            .filter(|k| ! keep.contains(k))
+            .copied()
+            .collect();
+        gone.iter().filter_map(|k| self.release(k)).collect()
+    }
+
+}
+
+//}}}
+
+//{{{ DrawIndexedArgs
+
+// layout of one indirect indexed draw
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DrawIndexedArgs {
+    pub index_count: u32,
+    pub instance_count: u32,
+    pub first_index: u32,
+    pub base_vertex: i32,
+    pub first_instance: u32,
+}
+
+impl DrawIndexedArgs {
+    pub const fn size_of() -> usize { std::mem::size_of::<Self>() }
+
+    pub fn as_mem(args: &[Self]) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(args.as_ptr() as *const u8, args.len() * Self::size_of()) }
+    }
+}
+
+// the draw of a reserved mesh and the bounds it covers
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MeshDraw {
+    pub args: DrawIndexedArgs,
+    pub bounds_min: Vec3,
+    pub bounds_max: Vec3,
+}
+
+//}}}
+
+//{{{ BufferStats
+
+#[derive(Clone, Debug, Default)]
+pub struct BufferStats {
+    pub occupancy: Vec<(usize, usize)>, // (bytes allocated, bytes) per buffer
+    pub expanded: usize,                // buffers in use by the pool
+    pub meshes: usize,                  // reserved meshes
+    pub free_spans: usize,              // holes after merging, one per allocator when compact
+    pub moved: usize,                   // meshes compacted by the last update
+    pub unplaced: usize,                // in view but too large for the room left, not drawn
+    pub deferred: usize,                // new meshes waiting on the upload budget
+    pub uploaded: usize,                // bytes staged by the last update
+    pub copies: usize,                  // staging copies recorded for them
+    pub draws: usize,                   // indexed draws issued per frame
+    pub culled: usize,                  // resident but outside the frustum
+    pub draw_dist: Option<i32>,         // chunks drawn around the camera's, None for all the world has
+    pub dropped: usize,                 // visible but past draw_dist
+    pub upload_time: Duration,
+}
+
+impl BufferStats {
+    // fill past which left out meshes count as out of memory rather than fragmentation
+    pub const FULL: f64 = 0.9;
+
+    pub fn allocated(&self) -> usize { self.occupancy.iter().map(|o| o.0).sum() }
+    pub fn capacity(&self) -> usize { self.occupancy.iter().map(|o| o.1).sum() }
+    // of every buffer the budget allows, expanded or not
+    pub fn fill(&self) -> f64 { self.allocated() as f64 / self.capacity().max(1) as f64 }
+    // every buffer is in use and nearly filled, and meshes were left out
+    pub fn is_full(&self) -> bool {
+        self.unplaced > 0 && self.expanded >= self.occupancy.len() && self.fill() >= Self::FULL
+    }
+}
+
+impl std::fmt::Display for BufferStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        const MIB: f64 = 1024.0 * 1024.0;
+        let per_buffer: Vec<String> = self.occupancy.iter().take(self.expanded)
+            .map(|(u, c)| format!("{:.0}%", 100.0 * *u as f64 / (*c).max(1) as f64))
+            .collect();
+        write!(f, "buffers {:.1}/{:.1} MiB [{}] meshes {} unplaced {} spans {} moved {}, draws {} culled {}, \
+            upload {:.2} ms {:.1} KiB in {} copies deferred {}",
+            self.allocated() as f64 / MIB, self.capacity() as f64 / MIB, per_buffer.join(" "),
+            self.meshes, self.unplaced, self.free_spans, self.moved,
+            self.draws, self.culled,
+            self.upload_time.as_secs_f64() * 1000.0, self.uploaded as f64 / 1024.0, self.copies, self.deferred,
+        )?;
+        if let Some(dist) = self.draw_dist {
+            write!(f, ", draw distance {} dropped {}", dist, self.dropped)?;
+        }
+        Ok(())
+    }
+}
+
+//}}}
+
+//{{{ PoolSizing
+
+// managed buffer sizes, from the device limits and a memory budget
+#[cfg(feature = "window")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PoolSizing {
+    pub vertex_buffer_size: usize,
+    pub index_buffer_size: usize,
+    pub max_buffers: usize,
+    pub max_draws: usize, // per buffer
+}
+
+#[cfg(feature = "window")]
+impl PoolSizing {
+
+    // smaller buffers waste less of the budget on a part filled last pool
+    pub const MIN_BUFFERS: u64 = 4;
+    pub const MAX_INDEX_BUFFER: u64 = 64 << 20;
+    pub const MIN_INDEX_BUFFER: u64 = 1 << 20;
+
+    pub fn new(limits: &Limits, budget: u64) -> Self {
+        // about 5 indices per vertex, 20 bytes of indices against a 16 byte vertex
+        // so a pool is 7/4 of its index buffer
+        let index_buffer_size = (budget / Self::MIN_BUFFERS / 7 * 4)
+            .clamp(Self::MIN_INDEX_BUFFER, Self::MAX_INDEX_BUFFER)
+            .min(limits.max_buffer_size)
+            / 64 * 64;
+        let vertex_buffer_size = index_buffer_size / 4 * 3;
+        let draws = (IndexedBufferManager::MAX_DRAWS * DrawIndexedArgs::size_of()) as u64;
+        let max_buffers = (budget / (index_buffer_size + vertex_buffer_size + draws)).clamp(1, u16::MAX as u64) as usize;
+        // the gpu culler binds every buffer's candidates at once
+        let max_draws = IndexedBufferManager::MAX_DRAWS
+            .min(limits.max_storage_buffer_binding_size as usize / (max_buffers * cull::Candidate::size_of()))
+            .max(1);
+        Self {
+            vertex_buffer_size: vertex_buffer_size as usize,
+            index_buffer_size: index_buffer_size as usize,
+            max_buffers,
+            max_draws,
+        }
+    }
+
+    // bytes once every buffer is created
+    pub fn budget(&self) -> usize {
+        self.max_buffers * (self.vertex_buffer_size + self.index_buffer_size + self.max_draws * DrawIndexedArgs::size_of())
     }
 
 }
@@ -670,90 +1122,388 @@ impl BucketPool {
 
 //{{{ IndexedBufferManager
 
+#[cfg(feature = "window")]
 pub struct IndexedBufferManager
 {
-    pub num_buffers: usize,
-    pub vertex_buffer_size: usize,
-    pub vertex_bucket_size: usize,
-    pub index_buffer_size: usize,
-    pub index_bucket_size: usize,
-    pub num_buckets: usize,
-    pub buckets: BucketPool,
+    pub num_buffers: usize, // created so far, the pool adds them as it expands
+    pub sizing: PoolSizing,
+    pub max_draws: usize, // per buffer
+    pub pool: MeshPool,
     pub vertex_buffers: Vec<Buffer>,
     pub index_buffers: Vec<Buffer>,
+    // one draw per reserved mesh, the frame's in view draws compacted per buffer
+    pub mesh_draws: SeaHashMap<MeshAlloc, MeshDraw>,
+    pub in_view: Vec<MeshAlloc>, // sorted
+    pub draws: Vec<Vec<DrawIndexedArgs>>,
+    pub draw_buffers: Vec<Buffer>,
+    pub cull: bool,
+    pub culled: usize,
+    pub stale: SeaHashSet<ChunkKey>, // updated while culled, written once back in view
+    pub compact_budget: usize, // meshes moved per update, their writes count against upload_budget
+    pub moved: usize,
+    pub unplaced: usize,
+    pub deferred: usize,
+    pub belt: util::StagingBelt,
+    pub upload_budget: usize, // staged bytes per update, at least one mesh goes through
+    pub uploaded: usize,
+    pub copies: usize,
+    pub upload_time: Duration, // last update, recorded copies only
+    // out of mesh memory the farthest chunks aren't drawn, the world's view distance is left alone
+    pub draw_dist: Option<i32>, // chebyshev chunk distance from the camera's chunk, None for no limit
+    pub farthest: i32,          // of the visible chunks, last update
+    pub dropped: usize,
+    pub calm: usize,            // updates since draw_dist last changed
 }
 
+#[cfg(feature = "window")]
 impl IndexedBufferManager
 {
 
-    pub fn new(device: &Device, num_buffers: usize) -> Self {
-        let vertex_bucket_size = IndexedMesh::MAX_VERTS_MEM;
-        let vertex_buffer_size = Limits::downlevel_defaults().max_buffer_size as usize;
-        let num_buckets = vertex_buffer_size / vertex_bucket_size;
-        let index_bucket_size = IndexedMesh::MAX_INDEX_MEM;
-        let index_buffer_size = index_bucket_size * num_buckets;
+    pub const MAX_DRAWS: usize = 16384;
+    pub const STAGING_CHUNK: u64 = 4 << 20;
+    pub const MIN_DRAW_DIST: i32 = 2;
+    // draw_dist grows back below this fill, well under BufferStats::FULL so one ring doesn't flip it back
+    pub const GROW_FILL: f64 = 0.7;
+    pub const GROW_UPDATES: usize = 60;
+
+    pub fn new(device: &Device, sizing: PoolSizing) -> Self {
+        let max_draws = sizing.max_draws;
+        let mut manager = Self {
+            num_buffers: 0,
+            sizing,
+            max_draws,
+            pool: MeshPool::new(
+                sizing.max_buffers as u16,
+                (sizing.vertex_buffer_size / TerrainVertex::size_of()) as u32,
+                (sizing.index_buffer_size / 4) as u32,
+                max_draws,
+            ),
+            vertex_buffers: vec![],
+            index_buffers: vec![],
+            mesh_draws: SeaHashMap::new(),
+            in_view: vec![],
+            draws: vec![],
+            draw_buffers: vec![],
+            cull: true,
+            culled: 0,
+            stale: SeaHashSet::new(),
+            compact_budget: 32,
+            moved: 0,
+            unplaced: 0,
+            deferred: 0,
+            belt: util::StagingBelt::new(Self::STAGING_CHUNK),
+            upload_budget: 8 << 20,
+            uploaded: 0,
+            copies: 0,
+            upload_time: Duration::ZERO,
+            draw_dist: None,
+            farthest: 0,
+            dropped: 0,
+            calm: 0,
+        };
+        // the pool starts with one buffer expanded
+        if ! manager.add_buffers(device) {
+            log::error!("no room for a {} MiB mesh buffer", sizing.index_buffer_size >> 20);
+        }
+        manager
+    }
 
+    // one more set of vertex, index and draw buffers, false when out of memory
+    pub fn add_buffers(&mut self, device: &Device) -> bool {
         let v_desc = &BufferDescriptor {
-                size: vertex_buffer_size as u64,
+                size: self.sizing.vertex_buffer_size as u64,
                 usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
                 mapped_at_creation: false,
                 label: Some("Managed Vertex Buffer"),
         };
 
         let i_desc = &BufferDescriptor {
-                size: index_buffer_size as u64,
+                size: self.sizing.index_buffer_size as u64,
                 usage: BufferUsages::INDEX | BufferUsages::COPY_DST,
                 mapped_at_creation: false,
                 label: Some("Managed Index Buffer"),
         };
 
-        let mut vertex_buffers = vec![];
-        let mut index_buffers = vec![];
-        for i in 0..num_buffers {
-            vertex_buffers.push(device.create_buffer(v_desc));
-            index_buffers.push(device.create_buffer(i_desc));
+        let d_desc = &BufferDescriptor {
+                size: (self.max_draws * DrawIndexedArgs::size_of()) as u64,
+                usage: BufferUsages::INDIRECT | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+                label: Some("Managed Draw Buffer"),
+        };
+
+        device.push_error_scope(ErrorFilter::OutOfMemory);
+        let buffers = (device.create_buffer(v_desc), device.create_buffer(i_desc), device.create_buffer(d_desc));
+        if let Some(err) = pollster::block_on(device.pop_error_scope()) {
+            log::warn!("mesh buffer {} not created: {}", self.num_buffers, err);
+            return false;
+        }
+        self.vertex_buffers.push(buffers.0);
+        self.index_buffers.push(buffers.1);
+        self.draw_buffers.push(buffers.2);
+        self.draws.push(vec![]);
+        self.num_buffers += 1;
+        true
+    }
+
+    // reserve, creating the buffers when the pool expands into a new one
+    // out of memory the pool stops at the buffers it has
+    fn reserve(&mut self, device: &Device, key: &ChunkKey, mesh: &IndexedMesh) -> Option<MeshAlloc> {
+        let a = self.pool.reserve(key, mesh.vertex_count(), mesh.index_count())?;
+        if (a.buffer as usize) < self.num_buffers || self.add_buffers(device) {
+            return Some(a);
         }
+        self.pool.release(key);
+        self.pool.limit(self.num_buffers as u16);
+        None
+    }
 
-        Self {
-            num_buffers,
-            vertex_buffer_size,
-            vertex_bucket_size,
-            index_buffer_size,
-            index_bucket_size,
-            num_buckets,
-            buckets: BucketPool::new(num_buffers as u16, num_buckets as u16),
-            vertex_buffers,
-            index_buffers,
+    pub fn stats(&self) -> BufferStats {
+        BufferStats {
+            occupancy: self.pool.occupancy(),
+            expanded: self.pool.cur_dim as usize,
+            meshes: self.pool.len(),
+            free_spans: self.pool.free_spans(),
+            moved: self.moved,
+            unplaced: self.unplaced,
+            deferred: self.deferred,
+            uploaded: self.uploaded,
+            copies: self.copies,
+            draws: self.draws.iter().map(|d| d.len()).sum(),
+            culled: self.culled,
+            draw_dist: self.draw_dist,
+            dropped: self.dropped,
+            upload_time: self.upload_time,
+        }
+    }
+
+    // one ring closer each update the buffers are full, one further after a while with room to spare
+    fn adjust_draw_dist(&mut self) {
+        let stats = self.stats();
+        self.calm += 1;
+        if stats.is_full() {
+            let dist = self.draw_dist.unwrap_or(self.farthest);
+            if dist > Self::MIN_DRAW_DIST {
+                log::warn!("mesh buffers full, draw distance {} -> {}", dist, dist - 1);
+                self.draw_dist = Some(dist - 1);
+                self.calm = 0;
+            }
+        }
+        else if let Some(dist) = self.draw_dist {
+            if stats.unplaced == 0 && stats.fill() < Self::GROW_FILL && self.calm >= Self::GROW_UPDATES {
+                self.draw_dist = if dist + 1 >= self.farthest {None} else {Some(dist + 1)};
+                self.calm = 0;
+            }
+        }
+    }
+
+    // draw of a mesh at its reservation
+    fn insert_draw(&mut self, a: MeshAlloc, mesh: &IndexedMesh) {
+        self.mesh_draws.insert(a, MeshDraw {
+            args: DrawIndexedArgs {
+                index_count: mesh.index_count() as u32,
+                instance_count: 1,
+                first_index: a.index.offset,
+                ..Default::default()
+            },
+            bounds_min: mesh.bounds_min,
+            bounds_max: mesh.bounds_max,
+        });
+    }
+
+    // bytes a mesh takes to upload
+    fn upload_size(mesh: &IndexedMesh) -> usize {
+        mesh.vertex_count() * TerrainVertex::size_of() + mesh.index_count() * 4
+    }
+
+    // writes sorted by span, runs of adjacent spans share one staging copy
+    fn upload_runs(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        writes: &mut Vec<(ChunkKey, MeshAlloc, &IndexedMesh)>,
+        indices: bool,
+    ) {
+        let span = |a: &MeshAlloc| if indices {a.index} else {a.vertex};
+        let stride = if indices {4} else {TerrainVertex::size_of()} as u64;
+        writes.sort_by_key(|(_, a, _)| (a.buffer, span(a)));
+        let mut i = 0;
+        while i < writes.len() {
+            let first = writes[i].1;
+            let mut end = i + 1;
+            while end < writes.len()
+                && writes[end].1.buffer == first.buffer
+                && span(&writes[end - 1].1).end() == span(&writes[end].1).offset
+                && (span(&writes[end].1).end() - span(&first).offset) as u64 * stride <= Self::STAGING_CHUNK
+            {
+                end += 1;
+            }
+            let run = &writes[i .. end];
+            let start = span(&first).offset;
+            let size = (span(&run[run.len() - 1].1).end() - start) as u64 * stride;
+            let target = if indices {&self.index_buffers} else {&self.vertex_buffers};
+            let mut view = self.belt.write_buffer(
+                encoder, &target[first.buffer as usize], start as u64 * stride, BufferSize::new(size).unwrap(), device);
+            for (_, a, mesh) in run {
+                let at = ((span(a).offset - start) as u64 * stride) as usize;
+                if indices {
+                    // rebased on the vertex span straight into staging
+                    let dst = &mut view[at .. at + mesh.index_count() * 4];
+                    for (d, ind) in dst.chunks_exact_mut(4).zip(mesh.inds.iter()) {
+                        d.copy_from_slice(&(a.vertex.offset + ind).to_ne_bytes());
+                    }
+                } else {
+                    let src = mesh.vertex_array();
+                    view[at .. at + src.len()].copy_from_slice(src);
+                }
+            }
+            self.uploaded += size as usize;
+            self.copies += 1;
+            i = end;
         }
     }
 
-    pub fn update(&mut self, queue: &Queue, gamedata: &GameData) {
+    pub fn update(&mut self, device: &Device, queue: &Queue, encoder: &mut CommandEncoder, gamedata: &GameData) {
+        let start = Instant::now();
+        let frustum = Frustum::from_camera(&gamedata.camera.mat_proj, &gamedata.camera.mat_view);
         // world chunk triangles
         // index and vertex buffers correlated
         let (visible, updated) = (&gamedata.visible_meshes, &gamedata.updated_mesh_keys);
+        let camera_chunk = (Vec4::from_array(gamedata.camera.position).truncate() / gamedata.chunk_extent.max(1e-6))
+            .floor().as_ivec3();
+        let dist = |k: &ChunkKey| (k.coord() - camera_chunk).abs().max_element();
+        // visible can list a chunk more than once
+        let mut keys = SeaHashSet::with_capacity(visible.len());
+        self.farthest = 0;
+        for (k, _) in visible {
+            keys.insert(*k);
+            self.farthest = self.farthest.max(dist(k));
+        }
+        // past the draw distance is treated as out of view, its room freed below
+        self.dropped = 0;
+        if let Some(max) = self.draw_dist {
+            let all = keys.len();
+            keys.retain(|k| dist(k) <= max);
+            self.dropped = all - keys.len();
+        }
+        // free chunks not visible first, their draws go with them
+        // dropped chunks can leave the pool smaller than keys yet holding some of them
+        if self.pool.len() > keys.len() || self.dropped > 0
+        {
+            let removed = self.pool.keep_reserved(&keys);
+            for a in removed {
+                self.mesh_draws.remove(&a);
+            }
+            let reserved = &self.pool.reserved;
+            self.stale.retain(|k| reserved.contains_key(k));
+        }
+        let mut in_view = Vec::with_capacity(visible.len());
+        let mut writes = vec![];
+        let mut budget = self.upload_budget;
+        self.culled = 0;
+        self.unplaced = 0;
+        self.deferred = 0;
+        self.uploaded = 0;
+        self.copies = 0;
         for (key, mesh) in visible {
-            let c = self.buckets.reserve(key, updated);
-            match c {
-                None => {continue;}
-                Some(c) => {
-                    let v_buffer = &self.vertex_buffers[c.buffer as usize];
-                    let i_buffer = &self.index_buffers[c.buffer as usize];
-                    let vmem_offset = c.offset as u64 * self.vertex_bucket_size as u64;
-                    let imem_offset = c.offset as u64 * self.index_bucket_size as u64;
-                    let vcount_offset = c.offset as u32 * IndexedMesh::MAX_VERTS as u32;
-                    queue.write_buffer(v_buffer, vmem_offset, mesh.vertex_array());
-                    queue.write_buffer(i_buffer, imem_offset, &mesh.index_array(vcount_offset as u32));
+            if ! keys.remove(key) {continue;} // seen
+            let resident = self.pool.reserved.get(key).copied();
+            if self.cull && ! frustum.intersects_aabb(mesh.bounds_min, mesh.bounds_max) {
+                // stays resident, catch up on the update when seen again
+                if resident.is_some() {
+                    self.culled += 1;
+                    if updated.contains(key) {self.stale.insert(*key);}
+                }
+                continue;
+            }
+            let force = updated.contains(key) | self.stale.remove(key);
+            if resident.is_none() || force {
+                // over the frame's budget, resident meshes keep drawing their old data
+                if budget == 0 {
+                    match resident {
+                        Some(_) => {self.stale.insert(*key);}
+                        None => self.deferred += 1,
+                    }
+                    if resident.is_some() {in_view.push(*key);}
+                    continue;
+                }
+                budget = budget.saturating_sub(Self::upload_size(mesh));
+            }
+            let target = match resident {
+                Some(_) if ! force => None,
+                // same size, rewrite in place
+                Some(a) if MeshPool::fits(&a, mesh.vertex_count(), mesh.index_count()) => Some(a),
+                resident => {
+                    if resident.is_some() {
+                        let old = self.pool.release(key).unwrap();
+                        self.mesh_draws.remove(&old);
+                    }
+                    self.reserve(device, key, mesh)
                 }
+            };
+            if let Some(a) = target {
+                self.insert_draw(a, mesh);
+                if mesh.index_count() > 0 {writes.push((*key, a, *mesh));}
+            }
+            // meshes are never truncated, one that doesn't fit is skipped and counted
+            if self.pool.reserved.contains_key(key) {
+                in_view.push(*key);
+            } else {
+                self.unplaced += 1;
             }
         }
-        // free chunks not visible
-        if self.buckets.len() > visible.len()
-        {
-            let removed = self.buckets.keep_reserved(visible);
-            for c in removed {
-                let buffer = &self.index_buffers[c.buffer as usize];
-                let offset = c.offset as u64 * self.index_bucket_size as u64;
-                queue.write_buffer(buffer, offset, &[0; IndexedMesh::MAX_INDEX_MEM]);
+        // fill holes left by freed and resized meshes, a few moves per frame
+        // with what's left of the upload budget
+        // only meshes whose current data matches their reservation can move
+        self.moved = 0;
+        if budget > 0 && ! self.pool.is_compact() {
+            let mut meshes: SeaHashMap<ChunkKey, &IndexedMesh> = SeaHashMap::with_capacity(visible.len());
+            for (k, m) in visible {
+                meshes.insert(*k, *m);
+            }
+            let stale = &self.stale;
+            let moves = self.pool.compact(self.compact_budget, budget, |k, a| {
+                ! stale.contains(k)
+                    && meshes.get(k).is_some_and(|m| MeshPool::fits(a, m.vertex_count(), m.index_count()))
+            });
+            self.moved = moves.len();
+            for (key, from, to) in moves {
+                let mesh = *meshes.get(&key).unwrap();
+                self.mesh_draws.remove(&from);
+                self.insert_draw(to, mesh);
+                if mesh.index_count() > 0 {writes.push((key, to, mesh));}
+            }
+            // written earlier this update then moved, the old place may have a new owner
+            let reserved = &self.pool.reserved;
+            writes.retain(|(k, a, _)| reserved.get(k) == Some(a));
+        }
+        self.upload_runs(device, encoder, &mut writes, false);
+        self.upload_runs(device, encoder, &mut writes, true);
+        self.belt.finish();
+        let in_view = in_view.iter().filter_map(|k| self.pool.reserved.get(k).copied()).collect();
+        self.write_draws(queue, in_view);
+        self.upload_time = start.elapsed();
+        self.adjust_draw_dist();
+    }
+
+    // staging chunks come back once the frame's copies ran
+    pub fn recall(&mut self) {
+        self.belt.recall();
+    }
+
+    // rebuild the per buffer draw lists, empty meshes are skipped
+    pub fn write_draws(&mut self, queue: &Queue, mut in_view: Vec<MeshAlloc>) {
+        in_view.sort();
+        for d in self.draws.iter_mut() {d.clear();}
+        for c in in_view.iter() {
+            match self.mesh_draws.get(c) {
+                Some(draw) if draw.args.index_count > 0 => self.draws[c.buffer as usize].push(draw.args),
+                _ => {}
+            }
+        }
+        self.in_view = in_view;
+        for (i, d) in self.draws.iter().enumerate() {
+            if ! d.is_empty() {
+                queue.write_buffer(&self.draw_buffers[i], 0, DrawIndexedArgs::as_mem(d));
             }
         }
     }
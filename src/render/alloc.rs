@@ -0,0 +1,124 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+// SpanAllocator -- sub allocates a range of units (vertices, indices)
+// best fit from a size ordered free list (TLSF without the bitmaps),
+// freed spans merge with their free neighbours
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+pub struct Span {
+    //order dependent
+    pub offset: u32,
+    pub len: u32,
+}
+
+impl Span {
+    #[inline]
+    pub fn end(&self) -> u32 { self.offset + self.len }
+}
+
+#[derive(Clone, Debug)]
+pub struct SpanAllocator {
+    pub capacity: u32,
+    pub used: u32,
+    pub free_by_offset: BTreeMap<u32, u32>, // offset -> len
+    pub free_by_size: BTreeSet<(u32, u32)>, // (len, offset)
+}
+
+impl SpanAllocator {
+
+    pub fn new(capacity: u32) -> Self {
+        let mut s = Self {
+            capacity,
+            used: 0,
+            free_by_offset: BTreeMap::new(),
+            free_by_size: BTreeSet::new(),
+        };
+        s.insert_free(0, capacity);
+        s
+    }
+
+    fn insert_free(&mut self, offset: u32, len: u32) {
+        if len == 0 {return;}
+        self.free_by_offset.insert(offset, len);
+        self.free_by_size.insert((len, offset));
+    }
+
+    fn remove_free(&mut self, offset: u32, len: u32) {
+        self.free_by_offset.remove(&offset);
+        self.free_by_size.remove(&(len, offset));
+    }
+
+    // take len units from the front of a free span
+    fn carve(&mut self, offset: u32, free_len: u32, len: u32) -> Span {
+        self.remove_free(offset, free_len);
+        self.insert_free(offset + len, free_len - len);
+        self.used += len;
+        Span {offset, len}
+    }
+
+    // smallest free span that fits
+    pub fn alloc(&mut self, len: u32) -> Option<Span> {
+        if len == 0 {return None;}
+        let (free_len, offset) = *self.free_by_size.range((len, 0) ..).next()?;
+        Some(self.carve(offset, free_len, len))
+    }
+
+    // lowest free span starting before limit that fits, for compaction
+    pub fn alloc_below(&mut self, len: u32, limit: u32) -> Option<Span> {
+        if len == 0 {return None;}
+        let (offset, free_len) = self.free_by_offset.range(.. limit)
+            .map(|(o, l)| (*o, *l))
+            .find(|(_, l)| *l >= len)?;
+        Some(self.carve(offset, free_len, len))
+    }
+
+    // a span known to be free, split out of the free span holding it
+    pub fn alloc_at(&mut self, span: Span) -> Option<Span> {
+        if span.len == 0 {return None;}
+        let (offset, free_len) = self.free_by_offset.range(..= span.offset).next_back().map(|(o, l)| (*o, *l))?;
+        if offset + free_len < span.end() {return None;}
+        self.remove_free(offset, free_len);
+        self.insert_free(offset, span.offset - offset);
+        self.insert_free(span.end(), offset + free_len - span.end());
+        self.used += span.len;
+        Some(span)
+    }
+
+    // first free offset, allocations before it can't move down
+    pub fn first_free(&self) -> u32 { self.free_by_offset.keys().next().copied().unwrap_or(self.capacity) }
+
+    pub fn free(&mut self, span: Span) {
+        if span.len == 0 {return;}
+        self.used -= span.len;
+        let (mut offset, mut len) = (span.offset, span.len);
+        // merge with the span before
+        if let Some((o, l)) = self.free_by_offset.range(.. offset).next_back().map(|(o, l)| (*o, *l)) {
+            if o + l == offset {
+                self.remove_free(o, l);
+                offset = o;
+                len += l;
+            }
+        }
+        // and after
+        if let Some(l) = self.free_by_offset.get(&span.end()).copied() {
+            self.remove_free(span.end(), l);
+            len += l;
+        }
+        self.insert_free(offset, len);
+    }
+
+    #[inline]
+    pub fn free_spans(&self) -> usize { self.free_by_offset.len() }
+
+    pub fn largest_free(&self) -> u32 { self.free_by_size.iter().next_back().map_or(0, |s| s.0) }
+
+    // nothing free below the last allocation
+    pub fn is_compact(&self) -> bool {
+        match self.free_spans() {
+            0 => true,
+            1 => self.free_by_offset.iter().all(|(o, l)| o + l == self.capacity),
+            _ => false,
+        }
+    }
+
+}
@@ -0,0 +1,419 @@
+use wgpu::*;
+use glam::*;
+use crate::math::frustum::Frustum;
+
+use super::{*,
+    globals::CameraUniform,
+};
+
+// GPU culling -- a compute pass tests every resident mesh against the
+// frustum and against a depth pyramid built from the previous frame, and
+// writes compacted indirect draws per managed buffer for TerrainPass
+
+//{{{ CullUniform
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct CullUniform {
+    pub prev_view_proj: [[f32; 4]; 4],
+    pub planes: [[f32; 4]; 6],
+    pub pyramid: [f32; 4], // mip 0 width, height, mip count, 1 when built
+    pub counts: [u32; 4],  // candidates, draw slots per buffer, planes, unused
+}
+
+impl CullUniform {
+    pub const fn size_of() -> usize { std::mem::size_of::<Self>() }
+
+    pub fn as_mem(&self) -> &[u8; Self::size_of()] {
+        let arr = unsafe { std::mem::transmute::<&Self, &[u8; Self::size_of()]>(self) };
+        arr
+    }
+}
+
+// one resident mesh
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Candidate {
+    pub bounds_min: [f32; 4],
+    pub bounds_max: [f32; 4],
+    pub index_count: u32,
+    pub first_index: u32,
+    pub base_vertex: i32,
+    pub buffer: u32,
+}
+
+impl Candidate {
+    pub const fn size_of() -> usize { std::mem::size_of::<Self>() }
+}
+
+//}}}
+
+//{{{ HiZ
+
+// depth pyramid, mip 0 is half the depth texture
+pub struct HiZ {
+    pub texture: Texture,
+    pub view: TextureView,             // all mips, for culling
+    pub bind_groups: Vec<BindGroup>,   // one per mip
+    pub sizes: Vec<(u32, u32)>,
+}
+
+impl HiZ {
+    pub fn new(
+        device: &Device,
+        depth: &SimpleTexture,
+        depth_layout: &BindGroupLayout,
+        reduce_layout: &BindGroupLayout,
+    ) -> Self {
+        let (w, h) = (depth.texture.width().max(2) / 2, depth.texture.height().max(2) / 2);
+        let mips = 32 - w.max(h).leading_zeros();
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("HiZ Pyramid"),
+            size: Extent3d {width: w, height: h, depth_or_array_layers: 1},
+            mip_level_count: mips,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::R32Float,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::STORAGE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let mip_view = |level: u32| texture.create_view(&TextureViewDescriptor {
+            base_mip_level: level,
+            mip_level_count: std::num::NonZeroU32::new(1),
+            ..Default::default()
+        });
+
+        let mut bind_groups = vec![];
+        let mut sizes = vec![];
+        for level in 0 .. mips {
+            let dst = mip_view(level);
+            let bind_group = if level == 0 {
+                device.create_bind_group(&BindGroupDescriptor {
+                    layout: depth_layout,
+                    entries: &[
+                        BindGroupEntry {binding: 0, resource: BindingResource::TextureView(&depth.view)},
+                        BindGroupEntry {binding: 2, resource: BindingResource::TextureView(&dst)},
+                    ],
+                    label: Some("HiZ Depth"),
+                })
+            } else {
+                let src = mip_view(level - 1);
+                device.create_bind_group(&BindGroupDescriptor {
+                    layout: reduce_layout,
+                    entries: &[
+                        BindGroupEntry {binding: 1, resource: BindingResource::TextureView(&src)},
+                        BindGroupEntry {binding: 2, resource: BindingResource::TextureView(&dst)},
+                    ],
+                    label: Some("HiZ Reduce"),
+                })
+            };
+            bind_groups.push(bind_group);
+            sizes.push(((w >> level).max(1), (h >> level).max(1)));
+        }
+
+        Self {
+            texture,
+            view,
+            bind_groups,
+            sizes,
+        }
+    }
+
+    pub fn mips(&self) -> u32 { self.sizes.len() as u32 }
+}
+
+//}}}
+
+//{{{ GpuCuller
+
+pub struct GpuCuller {
+    pub num_buffers: usize,
+    pub max_draws: usize,
+    pub uniform_buffer: Buffer,
+    pub candidate_buffer: Buffer,
+    pub draw_buffer: Buffer,   // max_draws slots per managed buffer
+    pub count_buffer: Buffer,  // draws per managed buffer
+    pub cull_layout: BindGroupLayout,
+    pub depth_layout: BindGroupLayout,
+    pub reduce_layout: BindGroupLayout,
+    pub cull_bind_group: BindGroup,
+    pub cull_pipeline: ComputePipeline,
+    pub depth_pipeline: ComputePipeline,
+    pub reduce_pipeline: ComputePipeline,
+    pub hiz: HiZ,
+    pub hiz_ready: bool,
+    pub candidates: Vec<Candidate>,
+    pub per_buffer: Vec<u32>,  // candidates per managed buffer, upper bound on draws
+    pub view_proj: Mat4,       // camera the pyramid was built with
+}
+
+impl GpuCuller {
+// new {{{
+    pub fn new(device: &Device, depth: &SimpleTexture, num_buffers: usize, max_draws: usize) -> Self {
+        let uniform_buffer = device.create_buffer(&BufferDescriptor {
+            size: CullUniform::size_of() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+            label: Some("Cull Uniform"),
+        });
+        let (candidate_buffer, draw_buffer, count_buffer) = Self::slot_buffers(device, num_buffers, max_draws);
+
+        let storage = |binding: u32, read_only: bool| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage {read_only},
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let texture = |binding: u32, sample_type: TextureSampleType| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Texture {
+                sample_type,
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let store = BindGroupLayoutEntry {
+            binding: 2,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::StorageTexture {
+                access: StorageTextureAccess::WriteOnly,
+                format: TextureFormat::R32Float,
+                view_dimension: TextureViewDimension::D2,
+            },
+            count: None,
+        };
+
+        let cull_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(1, true),
+                storage(2, false),
+                storage(3, false),
+                texture(4, TextureSampleType::Float {filterable: false}),
+            ],
+            label: Some("Cull Layout"),
+        });
+        let depth_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[texture(0, TextureSampleType::Depth), store],
+            label: Some("HiZ Depth Layout"),
+        });
+        let reduce_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[texture(1, TextureSampleType::Float {filterable: false}), store],
+            label: Some("HiZ Reduce Layout"),
+        });
+
+        let cull_shader = device.create_shader_module(ShaderModuleDescriptor {
+            source: ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!("cull.wgsl"))),
+            label: Some("Cull Shader"),
+        });
+        let hiz_shader = device.create_shader_module(ShaderModuleDescriptor {
+            source: ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!("hiz.wgsl"))),
+            label: Some("HiZ Shader"),
+        });
+        let pipeline = |layout: &BindGroupLayout, module: &ShaderModule, entry_point: &str| {
+            let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+                label: Some(entry_point),
+            });
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                layout: Some(&pipeline_layout),
+                module,
+                entry_point,
+                label: Some(entry_point),
+            })
+        };
+        let cull_pipeline = pipeline(&cull_layout, &cull_shader, "cull_main");
+        let depth_pipeline = pipeline(&depth_layout, &hiz_shader, "reduce_depth");
+        let reduce_pipeline = pipeline(&reduce_layout, &hiz_shader, "reduce");
+
+        let hiz = HiZ::new(device, depth, &depth_layout, &reduce_layout);
+        let cull_bind_group = Self::cull_bind_group(device, &cull_layout,
+            &uniform_buffer, &candidate_buffer, &draw_buffer, &count_buffer, &hiz);
+
+        Self {
+            num_buffers,
+            max_draws,
+            uniform_buffer,
+            candidate_buffer,
+            draw_buffer,
+            count_buffer,
+            cull_layout,
+            depth_layout,
+            reduce_layout,
+            cull_bind_group,
+            cull_pipeline,
+            depth_pipeline,
+            reduce_pipeline,
+            hiz,
+            hiz_ready: false,
+            candidates: vec![],
+            per_buffer: vec![0; num_buffers],
+            view_proj: Mat4::IDENTITY,
+        }
+    }
+//}}}
+
+    // candidates, draws and counts for num_buffers managed buffers
+    fn slot_buffers(device: &Device, num_buffers: usize, max_draws: usize) -> (Buffer, Buffer, Buffer) {
+        let slots = num_buffers * max_draws;
+        let candidate_buffer = device.create_buffer(&BufferDescriptor {
+            size: (slots * Candidate::size_of()) as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+            label: Some("Cull Candidates"),
+        });
+        let draw_buffer = device.create_buffer(&BufferDescriptor {
+            size: (slots * DrawIndexedArgs::size_of()) as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::INDIRECT | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+            label: Some("Cull Draws"),
+        });
+        let count_buffer = device.create_buffer(&BufferDescriptor {
+            size: (num_buffers * 4) as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::INDIRECT | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+            label: Some("Cull Counts"),
+        });
+        (candidate_buffer, draw_buffer, count_buffer)
+    }
+
+    // follow the manager as it adds buffers
+    pub fn grow(&mut self, device: &Device, num_buffers: usize) {
+        if num_buffers <= self.num_buffers {return;}
+        let (candidate_buffer, draw_buffer, count_buffer) = Self::slot_buffers(device, num_buffers, self.max_draws);
+        self.cull_bind_group = Self::cull_bind_group(device, &self.cull_layout,
+            &self.uniform_buffer, &candidate_buffer, &draw_buffer, &count_buffer, &self.hiz);
+        self.candidate_buffer = candidate_buffer;
+        self.draw_buffer = draw_buffer;
+        self.count_buffer = count_buffer;
+        self.num_buffers = num_buffers;
+        self.per_buffer.resize(num_buffers, 0);
+        // the new candidate buffer is empty, written again on update
+        self.candidates.clear();
+    }
+
+    fn cull_bind_group(
+        device: &Device,
+        layout: &BindGroupLayout,
+        uniform: &Buffer,
+        candidates: &Buffer,
+        draws: &Buffer,
+        counts: &Buffer,
+        hiz: &HiZ,
+    ) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            layout,
+            entries: &[
+                BindGroupEntry {binding: 0, resource: uniform.as_entire_binding()},
+                BindGroupEntry {binding: 1, resource: candidates.as_entire_binding()},
+                BindGroupEntry {binding: 2, resource: draws.as_entire_binding()},
+                BindGroupEntry {binding: 3, resource: counts.as_entire_binding()},
+                BindGroupEntry {binding: 4, resource: BindingResource::TextureView(&hiz.view)},
+            ],
+            label: Some("Cull"),
+        })
+    }
+
+    // the pyramid follows the depth texture
+    pub fn resize(&mut self, device: &Device, depth: &SimpleTexture) {
+        self.hiz = HiZ::new(device, depth, &self.depth_layout, &self.reduce_layout);
+        self.hiz_ready = false;
+        self.cull_bind_group = Self::cull_bind_group(device, &self.cull_layout,
+            &self.uniform_buffer, &self.candidate_buffer, &self.draw_buffer, &self.count_buffer, &self.hiz);
+    }
+
+    // every resident mesh is a candidate, the gpu decides what is drawn
+    pub fn update(&mut self, device: &Device, queue: &Queue, buffers: &IndexedBufferManager, camera: &CameraUniform) {
+        self.grow(device, buffers.num_buffers);
+        let mut candidates = Vec::with_capacity(buffers.in_view.len());
+        self.per_buffer.iter_mut().for_each(|n| *n = 0);
+        for c in buffers.in_view.iter() {
+            match buffers.mesh_draws.get(c) {
+                Some(draw) if draw.args.index_count > 0 => {
+                    self.per_buffer[c.buffer as usize] += 1;
+                    candidates.push(Candidate {
+                        bounds_min: draw.bounds_min.extend(1.0).to_array(),
+                        bounds_max: draw.bounds_max.extend(1.0).to_array(),
+                        index_count: draw.args.index_count,
+                        first_index: draw.args.first_index,
+                        base_vertex: draw.args.base_vertex,
+                        buffer: c.buffer as u32,
+                    });
+                }
+                _ => {}
+            }
+        }
+        if candidates != self.candidates && ! candidates.is_empty() {
+            let bytes = unsafe {
+                std::slice::from_raw_parts(candidates.as_ptr() as *const u8, candidates.len() * Candidate::size_of())
+            };
+            queue.write_buffer(&self.candidate_buffer, 0, bytes);
+        }
+        self.candidates = candidates;
+
+        let view_proj = Mat4::from_cols_array_2d(&camera.mat_proj) * Mat4::from_cols_array_2d(&camera.mat_view);
+        let frustum = Frustum::from_matrix(view_proj);
+        let mut planes = [[0.0; 4]; 6];
+        for (plane, p) in planes.iter_mut().zip(frustum.planes[.. frustum.count].iter()) {
+            *plane = p.to_array();
+        }
+        let (w, h) = self.hiz.sizes[0];
+        let uniform = CullUniform {
+            prev_view_proj: self.view_proj.to_cols_array_2d(),
+            planes,
+            pyramid: [w as f32, h as f32, self.hiz.mips() as f32, if self.hiz_ready {1.0} else {0.0}],
+            counts: [self.candidates.len() as u32, self.max_draws as u32, frustum.count as u32, 0],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, uniform.as_mem());
+        // the depth drawn this frame is seen with this camera
+        self.view_proj = view_proj;
+    }
+
+    // before the terrain pass
+    pub fn cull(&self, encoder: &mut CommandEncoder) {
+        // unused slots stay zero, which draws nothing
+        encoder.clear_buffer(&self.draw_buffer, 0, None);
+        encoder.clear_buffer(&self.count_buffer, 0, None);
+        if self.candidates.is_empty() {return;}
+        let mut cpass = encoder.begin_compute_pass(&ComputePassDescriptor {label: Some("Cull Pass")});
+        cpass.set_pipeline(&self.cull_pipeline);
+        cpass.set_bind_group(0, &self.cull_bind_group, &[]);
+        cpass.dispatch_workgroups((self.candidates.len() as u32).div_ceil(64), 1, 1);
+    }
+
+    // after the terrain pass, from its depth
+    pub fn build_pyramid(&mut self, encoder: &mut CommandEncoder) {
+        let mut cpass = encoder.begin_compute_pass(&ComputePassDescriptor {label: Some("HiZ Pass")});
+        for (level, bind_group) in self.hiz.bind_groups.iter().enumerate() {
+            let (w, h) = self.hiz.sizes[level];
+            cpass.set_pipeline(if level == 0 {&self.depth_pipeline} else {&self.reduce_pipeline});
+            cpass.set_bind_group(0, bind_group, &[]);
+            cpass.dispatch_workgroups(w.div_ceil(8), h.div_ceil(8), 1);
+        }
+        self.hiz_ready = true;
+    }
+
+    // slot range of one managed buffer in the draw buffer
+    pub fn draw_offset(&self, buffer: usize) -> BufferAddress {
+        (buffer * self.max_draws * DrawIndexedArgs::size_of()) as BufferAddress
+    }
+}
+
+//}}}
@@ -0,0 +1,114 @@
+// chunk culling, one thread per resident mesh
+// frustum against the current camera, occlusion against the depth pyramid
+// of the previous frame (reversed z, so the pyramid keeps the farthest = min depth)
+
+struct Cull {
+    prev_view_proj: mat4x4<f32>,
+    planes: array<vec4<f32>, 6>,
+    pyramid: vec4<f32>, // mip 0 width, height, mip count, 1 when built
+    counts: vec4<u32>,  // candidates, draw slots per buffer, planes, unused
+}
+
+struct Candidate {
+    bounds_min: vec4<f32>,
+    bounds_max: vec4<f32>,
+    index_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    buffer: u32,
+}
+
+struct DrawArgs {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> cull: Cull;
+@group(0) @binding(1)
+var<storage, read> candidates: array<Candidate>;
+@group(0) @binding(2)
+var<storage, read_write> draws: array<DrawArgs>;
+@group(0) @binding(3)
+var<storage, read_write> counts: array<atomic<u32>>;
+@group(0) @binding(4)
+var pyramid: texture_2d<f32>;
+
+fn in_frustum(lo: vec3<f32>, hi: vec3<f32>) -> bool {
+    for (var i = 0u; i < cull.counts.z; i++) {
+        let p = cull.planes[i];
+        let corner = select(lo, hi, p.xyz >= vec3<f32>(0.0));
+        if (dot(p.xyz, corner) + p.w < 0.0) {
+            return false;
+        }
+    }
+    return true;
+}
+
+fn farthest(level: i32, p: vec2<i32>) -> f32 {
+    let dims = vec2<i32>(textureDimensions(pyramid, level));
+    return textureLoad(pyramid, clamp(p, vec2<i32>(0), dims - 1), level).r;
+}
+
+fn occluded(lo: vec3<f32>, hi: vec3<f32>) -> bool {
+    if (cull.pyramid.w == 0.0) {
+        return false;
+    }
+    var ndc_lo = vec2<f32>(1.0);
+    var ndc_hi = vec2<f32>(-1.0);
+    var nearest = 0.0;
+    for (var i = 0u; i < 8u; i++) {
+        let corner = select(lo, hi, vec3<bool>((i & 1u) != 0u, (i & 2u) != 0u, (i & 4u) != 0u));
+        let clip = cull.prev_view_proj * vec4<f32>(corner, 1.0);
+        // crosses the near plane
+        if (clip.w <= 1e-4) {
+            return false;
+        }
+        let ndc = clip.xyz / clip.w;
+        ndc_lo = min(ndc_lo, ndc.xy);
+        ndc_hi = max(ndc_hi, ndc.xy);
+        nearest = max(nearest, ndc.z);
+    }
+    ndc_lo = clamp(ndc_lo, vec2<f32>(-1.0), vec2<f32>(1.0));
+    ndc_hi = clamp(ndc_hi, vec2<f32>(-1.0), vec2<f32>(1.0));
+
+    // texture space, y down
+    let uv_lo = vec2<f32>(ndc_lo.x, -ndc_hi.y) * 0.5 + 0.5;
+    let uv_hi = vec2<f32>(ndc_hi.x, -ndc_lo.y) * 0.5 + 0.5;
+    let size = (uv_hi - uv_lo) * cull.pyramid.xy;
+    // the level where the box covers at most 2x2 texels
+    let level = i32(clamp(ceil(log2(max(max(size.x, size.y), 1.0))), 0.0, cull.pyramid.z - 1.0));
+    let dims = vec2<f32>(textureDimensions(pyramid, level));
+    let p0 = vec2<i32>(uv_lo * dims);
+    let p1 = vec2<i32>(uv_hi * dims);
+    let depth = min(
+        min(farthest(level, p0), farthest(level, vec2<i32>(p1.x, p0.y))),
+        min(farthest(level, vec2<i32>(p0.x, p1.y)), farthest(level, p1)),
+    );
+    return nearest < depth;
+}
+
+@compute @workgroup_size(64)
+fn cull_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= cull.counts.x) {
+        return;
+    }
+    let c = candidates[id.x];
+    let lo = c.bounds_min.xyz;
+    let hi = c.bounds_max.xyz;
+    if (!in_frustum(lo, hi) || occluded(lo, hi)) {
+        return;
+    }
+    // compacted per buffer
+    let slot = atomicAdd(&counts[c.buffer], 1u);
+    var args: DrawArgs;
+    args.index_count = c.index_count;
+    args.instance_count = 1u;
+    args.first_index = c.first_index;
+    args.base_vertex = c.base_vertex;
+    args.first_instance = 0u;
+    draws[c.buffer * cull.counts.y + slot] = args;
+}
@@ -1,4 +1,6 @@
+#[cfg(feature = "window")]
 use wgpu::*;
+#[cfg(feature = "window")]
 use super::Pass;
 
 //{{{ CameraUniform
@@ -48,12 +50,72 @@ impl LightUniform {
 
 //}}}
 
+// ObjectUniform {{{
+
+// per object in the general triangle stream, indexed by instance
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct ObjectUniform {
+    pub model: [[f32; 4]; 4],
+    pub normal: [[f32; 4]; 4], // inverse transpose of model
+}
+
+impl ObjectUniform {
+    pub const fn size_of() -> usize { std::mem::size_of::<Self>() }
+
+    pub fn new(model: glam::DMat4) -> Self {
+        Self {
+            model: model.as_mat4().to_cols_array_2d(),
+            normal: model.inverse().transpose().as_mat4().to_cols_array_2d(),
+        }
+    }
+
+    pub fn as_mem(&self) -> &[u8; Self::size_of()] {
+        let arr = unsafe { std::mem::transmute::<&Self, &[u8; Self::size_of()]>(self) };
+        arr
+    }
+}
+
+//}}}
+
+// TerrainUniform {{{
+
+// what packed terrain vertices need to find their chunk
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TerrainUniform {
+    pub grid: [f32; 4],         // chunk extent, quantization min, range, unused
+    pub camera_chunk: [i32; 4], // chunk coordinates unwrap around this one
+}
+
+impl TerrainUniform {
+    pub const fn size_of() -> usize { std::mem::size_of::<Self>() }
+
+    pub fn new(extent: f32, camera: &CameraUniform) -> Self {
+        let extent = extent.max(1e-6);
+        let chunk = (glam::Vec4::from_array(camera.position).truncate() / extent).floor().as_ivec3();
+        Self {
+            grid: [extent, super::TerrainVertex::QUANT_MIN, super::TerrainVertex::QUANT_RANGE, 0.0],
+            camera_chunk: chunk.extend(0).to_array(),
+        }
+    }
+
+    pub fn as_mem(&self) -> &[u8; Self::size_of()] {
+        let arr = unsafe { std::mem::transmute::<&Self, &[u8; Self::size_of()]>(self) };
+        arr
+    }
+}
+
+//}}}
+
+#[cfg(feature = "window")]
 pub struct Globals {
     pub uniform_buffer: Buffer,
     pub bind_group_layout: BindGroupLayout,
     pub bind_group: BindGroup,
 }
 
+#[cfg(feature = "window")]
 impl Globals {
     pub fn new(
         device: &Device,
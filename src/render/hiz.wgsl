@@ -0,0 +1,45 @@
+// depth pyramid, each texel keeps the farthest (min, reversed z) depth
+// of the texels under it, 3x3 so odd sizes stay conservative
+
+@group(0) @binding(0)
+var depth: texture_depth_2d;
+@group(0) @binding(1)
+var src: texture_2d<f32>;
+@group(0) @binding(2)
+var dst: texture_storage_2d<r32float, write>;
+
+@compute @workgroup_size(8, 8)
+fn reduce_depth(@builtin(global_invocation_id) id: vec3<u32>) {
+    let out = vec2<i32>(id.xy);
+    let dims = vec2<i32>(textureDimensions(dst));
+    if (out.x >= dims.x || out.y >= dims.y) {
+        return;
+    }
+    let size = vec2<i32>(textureDimensions(depth));
+    var d = 1.0;
+    for (var y = 0; y < 3; y++) {
+        for (var x = 0; x < 3; x++) {
+            let p = clamp(out * 2 + vec2<i32>(x, y), vec2<i32>(0), size - 1);
+            d = min(d, textureLoad(depth, p, 0));
+        }
+    }
+    textureStore(dst, out, vec4<f32>(d, 0.0, 0.0, 0.0));
+}
+
+@compute @workgroup_size(8, 8)
+fn reduce(@builtin(global_invocation_id) id: vec3<u32>) {
+    let out = vec2<i32>(id.xy);
+    let dims = vec2<i32>(textureDimensions(dst));
+    if (out.x >= dims.x || out.y >= dims.y) {
+        return;
+    }
+    let size = vec2<i32>(textureDimensions(src));
+    var d = 1.0;
+    for (var y = 0; y < 3; y++) {
+        for (var x = 0; x < 3; x++) {
+            let p = clamp(out * 2 + vec2<i32>(x, y), vec2<i32>(0), size - 1);
+            d = min(d, textureLoad(src, p, 0).r);
+        }
+    }
+    textureStore(dst, out, vec4<f32>(d, 0.0, 0.0, 0.0));
+}
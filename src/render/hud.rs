@@ -0,0 +1,381 @@
+use std::collections::VecDeque;
+use wgpu::*;
+use glam::*;
+use crate::{
+    game::Game,
+    math::*,
+};
+
+use super::*;
+
+// Debug HUD -- text and graphs drawn over the finished frame in its own pass
+// the font is X11 misc-fixed 6x10 (public domain), 1 bit per pixel rows,
+// 16 glyphs per row of the image starting at ' '
+
+const FONT: &[u8] = include_bytes!("font6x10.raw");
+const GLYPH_W: u32 = 6;
+const GLYPH_H: u32 = 10;
+const ATLAS_COLS: u32 = 16;
+const ATLAS_ROWS: u32 = 6;
+
+//{{{ Hud
+
+pub struct Hud
+{
+    pub visible: bool,
+    pub scale: f32,                // pixels per font pixel
+    pub lines: Vec<String>,
+    pub frame_times: VecDeque<f32>, // ms, oldest first
+    pub buffer_usage: f32,         // 0..1
+}
+
+impl Default for Hud
+{
+    fn default() -> Self
+    {
+        Self
+        {
+            visible: true,
+            scale: 2.0,
+            lines: vec![],
+            frame_times: VecDeque::with_capacity(Self::GRAPH_FRAMES),
+            buffer_usage: 0.0,
+        }
+    }
+}
+
+impl Hud
+{
+    pub const GRAPH_FRAMES: usize = 240;
+    pub const GRAPH_MS: f32 = 50.0; // top of the graph
+
+    pub fn toggle(&mut self) { self.visible = ! self.visible; }
+
+    pub fn push_frame(&mut self, ms: f32)
+    {
+        if self.frame_times.len() >= Self::GRAPH_FRAMES {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(ms);
+    }
+
+    // once per frame before rendering
+    pub fn update(&mut self, elapsed_time: f64, game: &Game, buffers: Option<BufferStats>)
+    {
+        self.push_frame((elapsed_time * 1000.0) as f32);
+        if ! self.visible {return;}
+
+        let n = self.frame_times.len().max(1) as f32;
+        let avg = self.frame_times.iter().sum::<f32>() / n;
+        let max = self.frame_times.iter().fold(0.0f32, |a, b| a.max(*b));
+        let transform = game.get_render_transform();
+        let pos = transform.position;
+        let fwd = transform.forward();
+        let heading = fwd.x.atan2(fwd.z).to_degrees().rem_euclid(360.0);
+        let pitch = fwd.y.clamp(-1.0, 1.0).asin().to_degrees();
+        let chunk = game.world.current_chunk();
+        let stats = game.world.chunk_stats();
+
+        self.lines.clear();
+        self.lines.push(format!("fps {:.0}  frame {:.2} ms  max {:.2} ms", 1000.0 / avg.max(1e-3), avg, max));
+        self.lines.push(format!("pos {:.1} {:.1} {:.1}", pos.x, pos.y, pos.z));
+        self.lines.push(format!("chunk {} {} {}", chunk.x, chunk.y, chunk.z));
+        self.lines.push(format!("heading {:.0}  pitch {:.0}  {:?}", heading, pitch, game.player.mode));
+        self.lines.push(format!("queue chunk {} sfp {} mesh {} pending {}",
+            stats.queue_chunk, stats.queue_sfp, stats.queue_mesh, stats.pending));
+        self.lines.push(format!("chunks {} maps {} meshes {} {:.1} MiB",
+            stats.chunks, stats.surface_maps, stats.meshes, stats.bytes as f64 / (1024.0 * 1024.0)));
+        match buffers {
+            Some(b) => {
+                const MIB: f64 = 1024.0 * 1024.0;
+                self.buffer_usage = b.allocated() as f32 / b.capacity().max(1) as f32;
+                self.lines.push(format!("buffers {:.1}/{:.0} MiB in {}  meshes {} spans {} moved {}",
+                    b.allocated() as f64 / MIB, b.capacity() as f64 / MIB, b.expanded, b.meshes, b.free_spans, b.moved));
+                self.lines.push(format!("draws {} culled {} unplaced {}", b.draws, b.culled, b.unplaced));
+                self.lines.push(format!("upload {:.2} ms  {:.0} KiB in {} copies  deferred {}",
+                    b.upload_time.as_secs_f64() * 1000.0, b.uploaded as f64 / 1024.0, b.copies, b.deferred));
+            }
+            None => self.buffer_usage = 0.0,
+        }
+    }
+}
+
+//}}}
+
+//{{{ HudVertex
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct HudVertex {
+    pub position: [f32; 2], // clip space
+    pub uv: [f32; 2],       // negative for solid fill
+    pub color: [f32; 4],
+}
+
+impl HudVertex {
+    pub const fn size_of() -> usize { std::mem::size_of::<Self>() }
+}
+
+//}}}
+
+//{{{ HudPass
+
+pub struct HudPass {
+    pub font: SimpleTexture,
+    pub bind_group: BindGroup,
+    pub vertex_buffer: Buffer,
+    pub render_pipeline: RenderPipeline,
+    pub vertices: Vec<HudVertex>,
+    pub verts_count: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl HudPass {
+    pub const MAX_QUADS: usize = 8192;
+
+// new {{{
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        surface_config: &SurfaceConfiguration,
+    ) -> Self {
+        // expand the 1 bit font into a byte per texel
+        let atlas_w = ATLAS_COLS * GLYPH_W;
+        let atlas_h = ATLAS_ROWS * GLYPH_H;
+        let stride = (atlas_w as usize).div_ceil(8);
+        let mut texels = vec![0u8; (atlas_w * atlas_h) as usize];
+        for y in 0 .. atlas_h as usize {
+            for x in 0 .. atlas_w as usize {
+                if FONT[y * stride + x / 8] >> (7 - x % 8) & 1 == 1 {
+                    texels[y * atlas_w as usize + x] = 255;
+                }
+            }
+        }
+        let font = SimpleTexture::create_r8_texture(device, queue, atlas_w, atlas_h, &texels, "Hud Font");
+
+        let vertex_buffer = device.create_buffer(
+            &BufferDescriptor {
+                size: (Self::MAX_QUADS * 6 * HudVertex::size_of()) as u64,
+                usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+                label: Some("Hud Vertex Buffer"),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float {filterable: true},
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("Hud Layout"),
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&font.view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&font.sampler),
+                },
+            ],
+            label: Some("Hud"),
+        });
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            source: ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!("hud.wgsl"))),
+            label: Some("Hud Shader"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+            label: Some("Hud Pipeline Layout"),
+        });
+
+        let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                buffers: &[VertexBufferLayout {
+                    array_stride: HudVertex::size_of() as BufferAddress,
+                    step_mode: VertexStepMode::Vertex,
+                    attributes: &vertex_attr_array![
+                        0 => Float32x2,
+                        1 => Float32x2,
+                        2 => Float32x4,
+                    ]
+                }],
+                module: &shader,
+                entry_point: "vs_main",
+            },
+            fragment: Some(FragmentState {
+                targets: &[Some(ColorTargetState {
+                    format: surface_config.format,
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+                module: &shader,
+                entry_point: "fs_main",
+            }),
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            label: Some("Hud Pipeline"),
+            multisample: MultisampleState::default(),
+            multiview: None,
+        });
+
+        Self {
+            font,
+            bind_group,
+            vertex_buffer,
+            render_pipeline,
+            vertices: Vec::with_capacity(Self::MAX_QUADS * 6),
+            verts_count: 0,
+            width: surface_config.width,
+            height: surface_config.height,
+        }
+    }
+//}}}
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+    }
+
+    // rectangle in pixels from the top left
+    fn quad(&mut self, pos: Vec2, size: Vec2, uv0: Vec2, uv1: Vec2, color: [f32; 4]) {
+        if self.vertices.len() + 6 > Self::MAX_QUADS * 6 {return;}
+        let screen = vec2(self.width.max(1) as f32, self.height.max(1) as f32);
+        let clip = |p: Vec2| {
+            let c = p / screen * 2.0 - 1.0;
+            [c.x, -c.y]
+        };
+        let v = |p: Vec2, uv: Vec2| HudVertex {position: clip(p), uv: uv.to_array(), color};
+        let (p0, p1) = (pos, pos + size);
+        self.vertices.extend_from_slice(&[
+            v(p0, uv0),
+            v(vec2(p0.x, p1.y), vec2(uv0.x, uv1.y)),
+            v(p1, uv1),
+            v(p0, uv0),
+            v(p1, uv1),
+            v(vec2(p1.x, p0.y), vec2(uv1.x, uv0.y)),
+        ]);
+    }
+
+    fn rect(&mut self, pos: Vec2, size: Vec2, color: [f32; 4]) {
+        self.quad(pos, size, Vec2::splat(-1.0), Vec2::splat(-1.0), color);
+    }
+
+    fn text(&mut self, pos: Vec2, scale: f32, text: &str, color: [f32; 4]) {
+        let glyph = vec2(GLYPH_W as f32, GLYPH_H as f32);
+        let atlas = glyph * vec2(ATLAS_COLS as f32, ATLAS_ROWS as f32);
+        for (i, c) in text.chars().enumerate() {
+            let code = c as u32;
+            let index = if (0x20 .. 0x7F).contains(&code) {code - 0x20} else {'?' as u32 - 0x20};
+            if index == 0 {continue;}
+            let cell = vec2((index % ATLAS_COLS) as f32, (index / ATLAS_COLS) as f32) * glyph;
+            let at = pos + vec2(i as f32 * glyph.x * scale, 0.0);
+            self.quad(at, glyph * scale, cell / atlas, (cell + glyph) / atlas, color);
+        }
+    }
+
+    // builds the quads for this frame
+    pub fn update_hud(&mut self, queue: &Queue, hud: &Hud) {
+        self.vertices.clear();
+        if hud.visible {
+            let s = hud.scale;
+            let margin = 4.0 * s;
+            let line_h = GLYPH_H as f32 * s;
+            let graph_w = Hud::GRAPH_FRAMES as f32 * s;
+            let graph_h = 40.0 * s;
+            let text_w = hud.lines.iter().map(|l| l.len()).max().unwrap_or(0) as f32 * GLYPH_W as f32 * s;
+            let text_h = hud.lines.len() as f32 * line_h;
+
+            // backdrop
+            let panel = vec2(text_w.max(graph_w), text_h + graph_h + 3.0 * s + 2.0 * margin) + 2.0 * margin;
+            self.rect(Vec2::ZERO, panel, [0.0, 0.0, 0.0, 0.5]);
+
+            let mut y = margin;
+            for line in hud.lines.iter() {
+                self.text(vec2(margin, y), s, line, [1.0, 1.0, 1.0, 1.0]);
+                y += line_h;
+            }
+
+            // frame times, green under 60 Hz, yellow under 30 Hz
+            y += margin;
+            let px_per_ms = graph_h / Hud::GRAPH_MS;
+            for (i, ms) in hud.frame_times.iter().enumerate() {
+                let h = (ms.min(Hud::GRAPH_MS) * px_per_ms).max(1.0);
+                let color = if *ms <= 1000.0 / 60.0 {[0.2, 0.9, 0.2, 0.9]}
+                    else if *ms <= 1000.0 / 30.0 {[0.9, 0.8, 0.2, 0.9]}
+                    else {[0.9, 0.2, 0.2, 0.9]};
+                self.rect(vec2(margin + i as f32 * s, y + graph_h - h), vec2(s, h), color);
+            }
+            for ms in [1000.0 / 60.0, 1000.0 / 30.0] {
+                self.rect(vec2(margin, y + graph_h - ms * px_per_ms), vec2(graph_w, 1.0), [1.0, 1.0, 1.0, 0.4]);
+            }
+
+            // buffer usage
+            y += graph_h + margin;
+            self.rect(vec2(margin, y), vec2(graph_w, 3.0 * s), [0.3, 0.3, 0.3, 0.8]);
+            self.rect(vec2(margin, y), vec2(graph_w * hud.buffer_usage.clamp(0.0, 1.0), 3.0 * s), [0.3, 0.6, 1.0, 0.9]);
+        }
+        self.verts_count = self.vertices.len() as u32;
+        if self.verts_count > 0 {
+            let bytes = unsafe {
+                std::slice::from_raw_parts(self.vertices.as_ptr() as *const u8, self.vertices.len() * HudVertex::size_of())
+            };
+            queue.write_buffer(&self.vertex_buffer, 0, bytes);
+        }
+    }
+
+}
+
+impl Pass for HudPass {
+
+    fn draw(&mut self, view: &TextureView, encoder: &mut CommandEncoder) -> Result<(), SurfaceError>
+    {
+        if self.verts_count == 0 {return Ok(());}
+        // drawn over the previous pass
+        let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+            label: Some("Hud Pass"),
+        });
+        rpass.set_pipeline(&self.render_pipeline);
+        rpass.set_bind_group(0, &self.bind_group, &[]);
+        rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        rpass.draw(0..self.verts_count, 0..1);
+        Ok(())
+    }
+}
+
+//}}}
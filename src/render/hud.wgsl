@@ -0,0 +1,37 @@
+// vertex
+
+struct VertexInput {
+    @location(0) position: vec2<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(vert: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.position = vec4<f32>(vert.position, 0.0, 1.0);
+    out.uv = vert.uv;
+    out.color = vert.color;
+    return out;
+}
+
+// fragment
+
+@group(0) @binding(0)
+var font: texture_2d<f32>;
+@group(0) @binding(1)
+var font_sampler: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // negative uv is a solid fill, sampled anyway to keep control flow uniform
+    let glyph = textureSample(font, font_sampler, max(in.uv, vec2<f32>(0.0))).r;
+    let coverage = select(glyph, 1.0, in.uv.x < 0.0);
+    return vec4<f32>(in.color.rgb, in.color.a * coverage);
+}
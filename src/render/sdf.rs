@@ -208,7 +208,7 @@ impl SdfPass {
 
 impl Pass for SdfPass {
 
-    fn update(&mut self, queue: &Queue, gamedata: &GameData) {
+    fn update(&mut self, device: &Device, queue: &Queue, encoder: &mut CommandEncoder, gamedata: &GameData) {
 
         // load sdf directly into compute buffer
         
@@ -218,6 +218,10 @@ impl Pass for SdfPass {
         self.globals.update(queue, &gamedata.camera, &gamedata.light);
     }
 
+    fn resize(&mut self, device: &Device, config: &SurfaceConfiguration) {
+        self.depth_texture = SimpleTexture::create_depth_texture(device, config, "depth_texture");
+    }
+
     fn draw(&mut self, view: &TextureView, encoder: &mut CommandEncoder) -> Result<(), SurfaceError>
     {
         // compute pass
@@ -251,7 +255,8 @@ impl Pass for SdfPass {
         rpass.set_pipeline(&self.render_pipeline);
         rpass.set_bind_group(0, &self.globals.bind_group, &[]);
         rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-        rpass.draw(0..Gpu::max_verts() as u32, 0..1);
+        let verts = self.vertex_buffer.size() / Vertex::size_of() as u64;
+        rpass.draw(0..verts as u32, 0..1);
         drop(rpass);
         Ok(())
     }
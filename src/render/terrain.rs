@@ -10,11 +10,27 @@ use crate::{
 };
 
 use super::{*,
-    globals::Globals,
+    globals::{Globals, ObjectUniform, TerrainUniform},
+    cull::GpuCuller,
 };
 
+#[derive(Clone, Copy, Debug)]
 pub struct TerrainConfig {
+    pub gpu_culling: bool, // compute frustum and occlusion culling, replaces the cpu frustum test
+    pub vram_budget: u64,  // bytes for chunk meshes, buffers are created as they fill
+}
 
+impl TerrainConfig {
+    pub const DEFAULT_VRAM_BUDGET: u64 = 1 << 30;
+}
+
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        Self {
+            gpu_culling: false,
+            vram_budget: Self::DEFAULT_VRAM_BUDGET,
+        }
+    }
 }
 
 pub struct TerrainPass {
@@ -22,33 +38,62 @@ pub struct TerrainPass {
     pub globals: Globals,
     pub bind_group_layout: BindGroupLayout,
     pub bind_groups: HashMap<usize, BindGroup>,
+    pub terrain_buffer: Buffer,
+    pub terrain_bind_group: BindGroup,
     pub vertex_buffer_general: Buffer,
+    pub object_buffer: Buffer,
+    pub object_bind_group: BindGroup,
+    pub objects: Vec<ObjectDraw>,
     pub buffers: IndexedBufferManager,
     pub depth_texture: SimpleTexture,
     pub render_pipeline: RenderPipeline,
+    pub object_pipeline: RenderPipeline,
     pub verts_count: u32,
+    pub multi_draw: bool, // one indirect call per buffer, else a draw per mesh
+    pub multi_draw_count: bool, // draw count read from the gpu
+    pub culler: Option<GpuCuller>,
 }
 
 impl TerrainPass {
+    pub const MAX_OBJECTS: usize = 256;
+    pub const GENERAL_BUFFER_SIZE: u64 = 16 << 20;
+
 // new {{{
     pub fn new(
         config: &TerrainConfig,
         device: &Device,
         surface_config: &SurfaceConfiguration,
     ) -> Self {
-        let num_buffers = 10;
-        let max_buffer_size = Limits::downlevel_defaults().max_buffer_size;
+        let limits = device.limits();
 
         // buffers
 
         let vertex_buffer_general = device.create_buffer(
             &BufferDescriptor {
-                size: max_buffer_size,
+                size: Self::GENERAL_BUFFER_SIZE.min(limits.max_buffer_size),
                 usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
                 mapped_at_creation: false,
                 label: Some("General Vertex Buffer"),
         });
 
+        // model matrices for the general triangles
+        let object_buffer = device.create_buffer(
+            &BufferDescriptor {
+                size: (Self::MAX_OBJECTS * ObjectUniform::size_of()) as u64,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+                label: Some("Object Buffer"),
+        });
+
+        // grid the packed chunk vertices unpack against
+        let terrain_buffer = device.create_buffer(
+            &BufferDescriptor {
+                size: TerrainUniform::size_of() as u64,
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+                label: Some("Terrain Uniform"),
+        });
+
         let depth_texture = SimpleTexture::create_depth_texture(device, surface_config, "depth_texture");
 
         // bindgroups
@@ -61,7 +106,7 @@ impl TerrainPass {
                     ty: BindingType::Buffer {
                         ty: BufferBindingType::Uniform,
                         has_dynamic_offset: false,
-                        min_binding_size: BufferSize::new(Vertex::size_of() as BufferAddress)
+                        min_binding_size: BufferSize::new(TerrainUniform::size_of() as BufferAddress)
                     },
                     count: None,
                 },
@@ -69,12 +114,63 @@ impl TerrainPass {
             label: Some("Terrain Local Layout"),
         });
 
+        let terrain_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: terrain_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("Terrain"),
+        });
+
+        let object_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage {read_only: true},
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(ObjectUniform::size_of() as BufferAddress)
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("Object Layout"),
+        });
+
+        let object_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &object_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: object_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("Objects"),
+        });
+
         let shader_desc = ShaderModuleDescriptor {
             source: ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!("terrain.wgsl"))),
             label: Some("shader"),
         };
         let shader = device.create_shader_module(shader_desc);
 
+        // chunk meshes, see TerrainVertex
+        let terrain_layouts = [
+            VertexBufferLayout {
+                array_stride: TerrainVertex::size_of() as BufferAddress,
+                step_mode: VertexStepMode::Vertex,
+                attributes: &vertex_attr_array![
+                    0 => Uint16x4,
+                    1 => Snorm16x2,
+                    2 => Uint8x4,
+                ]
+            }
+        ];
+
         let vertex_layouts = [
             VertexBufferLayout {
                 array_stride: Vertex::size_of() as BufferAddress,
@@ -94,7 +190,7 @@ impl TerrainPass {
         let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             bind_group_layouts: &[
                 &globals.bind_group_layout,
-                //&bind_group_layout
+                &bind_group_layout,
             ],
             push_constant_ranges: &[],
             label: Some("Render Pipeline Layout"),
@@ -103,9 +199,9 @@ impl TerrainPass {
         let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
             layout: Some(&pipeline_layout),
             vertex: VertexState {
-                buffers: &vertex_layouts,
+                buffers: &terrain_layouts,
                 module: &shader,
-                entry_point: "vs_main",
+                entry_point: "vs_terrain",
             },
             fragment: Some(FragmentState {
                 targets: &[Some(ColorTargetState {
@@ -144,15 +240,86 @@ impl TerrainPass {
             multiview: None,
         });
 
+        // general triangles, non indexed with a model matrix per object
+        let object_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            bind_group_layouts: &[
+                &globals.bind_group_layout,
+                &object_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+            label: Some("Object Pipeline Layout"),
+        });
+
+        let object_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            layout: Some(&object_pipeline_layout),
+            vertex: VertexState {
+                buffers: &vertex_layouts,
+                module: &shader,
+                entry_point: "vs_object",
+            },
+            fragment: Some(FragmentState {
+                targets: &[Some(ColorTargetState {
+                    format: surface_config.format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+                module: &shader,
+                entry_point: "fs_main",
+            }),
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Ccw,
+                cull_mode: None, // loaded models aren't reliably wound
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: Some(DepthStencilState {
+                format: SimpleTexture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::Greater,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            label: Some("Object Pipeline"),
+            multisample: MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        let sizing = PoolSizing::new(&limits, config.vram_budget);
+        log::info!("mesh buffers {} x {} MiB, {} draws each", sizing.max_buffers,
+            (sizing.vertex_buffer_size + sizing.index_buffer_size) >> 20, sizing.max_draws);
+        let mut buffers = IndexedBufferManager::new(device, sizing);
+        let culler = if config.gpu_culling {
+            buffers.cull = false;
+            Some(GpuCuller::new(device, &depth_texture, buffers.num_buffers, buffers.max_draws))
+        } else {
+            None
+        };
+
         Self {
             globals,
             bind_group_layout,
             bind_groups: Default::default(),
+            terrain_buffer,
+            terrain_bind_group,
             vertex_buffer_general,
-            buffers: IndexedBufferManager::new(device, num_buffers),
+            object_buffer,
+            object_bind_group,
+            objects: vec![],
+            buffers,
             depth_texture,
             render_pipeline,
+            object_pipeline,
             verts_count: 0,
+            multi_draw: device.features().contains(Features::MULTI_DRAW_INDIRECT),
+            multi_draw_count: device.features().contains(Features::MULTI_DRAW_INDIRECT_COUNT),
+            culler,
         }
     }
 //}}}
@@ -161,22 +328,56 @@ impl TerrainPass {
 
 impl Pass for TerrainPass {
 
-    fn update(&mut self, queue: &Queue, gamedata: &GameData) {
-        // general purpose triangles
-        let mut i = 0;
-        for tri in &gamedata.general_triangles {
-            if i + 3 * Vertex::size_of() as u64 > Gpu::max_verts() { break; }
-            let dat = tri.to_array();
-            queue.write_buffer(&self.vertex_buffer_general, i, &dat);
-            i += 3 * Vertex::size_of() as u64;
+    fn update(&mut self, device: &Device, queue: &Queue, encoder: &mut CommandEncoder, gamedata: &GameData) {
+        // general purpose triangles, one write for all objects
+        let max_tris = (self.vertex_buffer_general.size() / (3 * Vertex::size_of()) as u64) as usize;
+        let tris = &gamedata.general_triangles[.. gamedata.general_triangles.len().min(max_tris)];
+        let mut dat = Vec::with_capacity(tris.len() * 3 * Vertex::size_of());
+        for tri in tris {
+            dat.extend_from_slice(&tri.to_array());
+        }
+        if ! dat.is_empty() {
+            queue.write_buffer(&self.vertex_buffer_general, 0, &dat);
+        }
+        self.verts_count = 3 * tris.len() as u32;
+
+        self.objects.clear();
+        let mut uniforms = Vec::with_capacity(gamedata.objects.len() * ObjectUniform::size_of());
+        for object in gamedata.objects.iter().take(Self::MAX_OBJECTS) {
+            if (object.first + object.count) as usize > tris.len() { continue; }
+            uniforms.extend_from_slice(object.uniform.as_mem());
+            self.objects.push(*object);
+        }
+        if ! uniforms.is_empty() {
+            queue.write_buffer(&self.object_buffer, 0, &uniforms);
+        }
+        self.buffers.update(device, queue, encoder, gamedata);
+        if let Some(culler) = &mut self.culler {
+            culler.update(device, queue, &self.buffers, &gamedata.camera);
         }
-        self.verts_count = i as u32;
-        self.buffers.update(queue, gamedata);
         self.globals.update(queue, &gamedata.camera, &gamedata.light);
+        queue.write_buffer(&self.terrain_buffer, 0, TerrainUniform::new(gamedata.chunk_extent, &gamedata.camera).as_mem());
+    }
+
+    fn recall(&mut self) { self.buffers.recall(); }
+
+    fn resize(&mut self, device: &Device, config: &SurfaceConfiguration) {
+        self.depth_texture = SimpleTexture::create_depth_texture(device, config, "depth_texture");
+        if let Some(culler) = &mut self.culler {
+            culler.resize(device, &self.depth_texture);
+        }
     }
 
+    fn upload_time(&self) -> Duration { self.buffers.upload_time }
+
+    fn buffer_stats(&self) -> Option<BufferStats> { Some(self.buffers.stats()) }
+
     fn draw(&mut self, view: &TextureView, encoder: &mut CommandEncoder) -> Result<(), SurfaceError>
     {
+        if let Some(culler) = &self.culler {
+            culler.cull(encoder);
+        }
+
         // render pass
         let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
             color_attachments: &[Some(RenderPassColorAttachment {
@@ -200,13 +401,58 @@ impl Pass for TerrainPass {
         });
         rpass.set_pipeline(&self.render_pipeline);
         rpass.set_bind_group(0, &self.globals.bind_group, &[]);
-        //TODO: general purpose triangles not drawn
-        
-        let max_inds = self.buffers.num_buckets as u32 * IndexedMesh::MAX_INDEX as u32;
-        for i in 0..self.buffers.num_buffers {
-            rpass.set_vertex_buffer(0, self.buffers.vertex_buffers[i].slice(..));
-            rpass.set_index_buffer(self.buffers.index_buffers[i].slice(..), IndexFormat::Uint32);
-            rpass.draw_indexed(0..max_inds, 0, 0..1);
+        rpass.set_bind_group(1, &self.terrain_bind_group, &[]);
+        if let Some(culler) = &self.culler {
+            // the cull pass wrote the draws, per_buffer bounds their count
+            let stride = DrawIndexedArgs::size_of() as BufferAddress;
+            for i in 0..self.buffers.num_buffers {
+                let max = culler.per_buffer[i];
+                if max == 0 {continue;}
+                let offset = culler.draw_offset(i);
+                rpass.set_vertex_buffer(0, self.buffers.vertex_buffers[i].slice(..));
+                rpass.set_index_buffer(self.buffers.index_buffers[i].slice(..), IndexFormat::Uint32);
+                if self.multi_draw_count {
+                    rpass.multi_draw_indexed_indirect_count(&culler.draw_buffer, offset, &culler.count_buffer, 4 * i as BufferAddress, max);
+                } else if self.multi_draw {
+                    // culled slots stay zeroed
+                    rpass.multi_draw_indexed_indirect(&culler.draw_buffer, offset, max);
+                } else {
+                    for j in 0..max as BufferAddress {
+                        rpass.draw_indexed_indirect(&culler.draw_buffer, offset + j * stride);
+                    }
+                }
+            }
+        } else {
+            for i in 0..self.buffers.num_buffers {
+                let draws = &self.buffers.draws[i];
+                if draws.is_empty() {continue;}
+                rpass.set_vertex_buffer(0, self.buffers.vertex_buffers[i].slice(..));
+                rpass.set_index_buffer(self.buffers.index_buffers[i].slice(..), IndexFormat::Uint32);
+                if self.multi_draw {
+                    rpass.multi_draw_indexed_indirect(&self.buffers.draw_buffers[i], 0, draws.len() as u32);
+                } else {
+                    for d in draws {
+                        rpass.draw_indexed(d.first_index .. d.first_index + d.index_count, d.base_vertex, 0..1);
+                    }
+                }
+            }
+        }
+
+        // general purpose triangles, the instance picks the model matrix
+        if ! self.objects.is_empty() {
+            rpass.set_pipeline(&self.object_pipeline);
+            rpass.set_bind_group(1, &self.object_bind_group, &[]);
+            rpass.set_vertex_buffer(0, self.vertex_buffer_general.slice(..));
+            for (i, object) in self.objects.iter().enumerate() {
+                let i = i as u32;
+                rpass.draw(3 * object.first .. 3 * (object.first + object.count), i .. i + 1);
+            }
+        }
+        drop(rpass);
+
+        // occluders for the next frame
+        if let Some(culler) = &mut self.culler {
+            culler.build_pyramid(encoder);
         }
 
         Ok(())
@@ -42,6 +42,77 @@ fn vs_main(vert: VertexInput) -> VertexOutput {
 	return out;
 }
 
+// chunk meshes, packed vertices (see TerrainVertex)
+
+struct Terrain {
+    grid: vec4<f32>,         // chunk extent, quantization min, range, unused
+    camera_chunk: vec4<i32>,
+}
+@group(1) @binding(0)
+var<uniform> terrain: Terrain;
+
+struct TerrainInput {
+    @location(0) position: vec4<u32>, // xyz quantized, w material
+    @location(1) normal: vec2<f32>,   // octahedral
+    @location(2) chunk: vec4<u32>,    // low 8 bits of the chunk coordinate
+};
+
+// matches TerrainVertex::PALETTE
+const PALETTE_SIZE: u32 = 2u;
+var<private> palette: array<vec4<f32>, 2> = array<vec4<f32>, 2>(
+    vec4<f32>(0.45, 0.55, 0.3, 1.0), // ground
+    vec4<f32>(0.5, 0.48, 0.45, 1.0), // rock
+);
+
+fn oct_decode(e: vec2<f32>) -> vec3<f32> {
+    let z = 1.0 - abs(e.x) - abs(e.y);
+    let t = max(-z, 0.0);
+    let xy = e - select(vec2<f32>(-t), vec2<f32>(t), e >= vec2<f32>(0.0));
+    return normalize(vec3<f32>(xy, z));
+}
+
+@vertex
+fn vs_terrain(vert: TerrainInput) -> VertexOutput {
+    // nearest chunk with these low bits
+    let cam = terrain.camera_chunk.xyz;
+    let low = vec3<i32>(vert.chunk.xyz);
+    let delta = ((low - (cam & vec3<i32>(255)) + 128) & vec3<i32>(255)) - 128;
+    let chunk = vec3<f32>(cam + delta);
+    let local = vec3<f32>(vert.position.xyz) / 65535.0 * terrain.grid.z + terrain.grid.y;
+    let world = (chunk + local) * terrain.grid.x;
+
+    var material = vert.position.w;
+    if (material >= PALETTE_SIZE) {
+        material = 0u;
+    }
+    var out: VertexOutput;
+    out.world_position = vec4<f32>(world, 1.0);
+    out.world_normal = vec4<f32>(oct_decode(vert.normal), 0.0);
+    out.position = globals.mat_proj * globals.mat_view * out.world_position;
+    out.color = palette[material];
+    return out;
+}
+
+// objects, model space vertices placed by a per object matrix
+
+struct Object {
+    model: mat4x4<f32>,
+    normal: mat4x4<f32>,
+}
+@group(1) @binding(0)
+var<storage, read> objects: array<Object>;
+
+@vertex
+fn vs_object(vert: VertexInput, @builtin(instance_index) instance: u32) -> VertexOutput {
+    let object = objects[instance];
+    var out: VertexOutput;
+    out.world_position = object.model * vert.position;
+    out.world_normal = vec4<f32>(normalize((object.normal * vec4<f32>(vert.normal.xyz, 0.0)).xyz), 1.0);
+    out.position = globals.mat_proj * globals.mat_view * out.world_position;
+    out.color = vert.color;
+    return out;
+}
+
 // fragment
 
 @fragment
@@ -0,0 +1,248 @@
+use glam::*;
+use crate::{
+    math::hasher::ChunkKey,
+    game::Game,
+    input::{InputState, AXIS_COUNT},
+};
+
+// Input recording -- the mapped input for every tick plus the world seed
+// bindings are applied before recording, a replay does not depend on them
+// the outcome of the recorded run is stored so a replay can be checked against it
+//
+// file layout, little endian
+//   magic "SDFR", version u32, seed u32, tick_rate f64, ticks u64
+//   per tick: held u32, pressed u32, axes f64 x AXIS_COUNT
+//   outcome: position f64 x 3, chunks u64, chunk keys [u8; 12] each
+
+pub const MAGIC: &[u8; 4] = b"SDFR";
+pub const VERSION: u32 = 1;
+pub const TICK_BYTES: usize = 8 + AXIS_COUNT * 8;
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct ReplayOutcome
+{
+    pub position: DVec3,
+    pub chunks: Vec<ChunkKey>, // sorted
+}
+
+impl ReplayOutcome
+{
+    pub fn from_game(game: &Game) -> Self
+    {
+        Self {
+            position: game.player.transform.position,
+            chunks: game.world.chunk_keys(),
+        }
+    }
+
+    // description of the first difference
+    pub fn compare(&self, other: &ReplayOutcome) -> Result<(), String>
+    {
+        if self.position != other.position {
+            return Err(format!("position {:?} != {:?}", other.position, self.position));
+        }
+        if self.chunks != other.chunks {
+            let missing = self.chunks.iter().filter(|k| ! other.chunks.contains(k)).count();
+            let extra = other.chunks.iter().filter(|k| ! self.chunks.contains(k)).count();
+            return Err(format!("chunks differ, {} missing {} extra", missing, extra));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Recording
+{
+    pub seed: u32,
+    pub tick_rate: f64,
+    pub ticks: Vec<InputState>,
+    pub outcome: ReplayOutcome,
+}
+
+impl Recording
+{
+
+    pub fn new(seed: u32, tick_rate: f64) -> Self
+    {
+        Self {seed, tick_rate, ..Default::default()}
+    }
+
+    pub fn push(&mut self, input: &InputState)
+    {
+        self.ticks.push(*input);
+    }
+
+    pub fn finish(&mut self, game: &Game)
+    {
+        self.outcome = ReplayOutcome::from_game(game);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8>
+    {
+        let mut b = Vec::with_capacity(32 + self.ticks.len() * TICK_BYTES);
+        b.extend_from_slice(MAGIC);
+        b.extend_from_slice(&VERSION.to_le_bytes());
+        b.extend_from_slice(&self.seed.to_le_bytes());
+        b.extend_from_slice(&self.tick_rate.to_le_bytes());
+        b.extend_from_slice(&(self.ticks.len() as u64).to_le_bytes());
+        for t in self.ticks.iter() {
+            b.extend_from_slice(&t.held.to_le_bytes());
+            b.extend_from_slice(&t.pressed.to_le_bytes());
+            for a in t.axes.iter() {
+                b.extend_from_slice(&a.to_le_bytes());
+            }
+        }
+        for v in self.outcome.position.to_array().iter() {
+            b.extend_from_slice(&v.to_le_bytes());
+        }
+        b.extend_from_slice(&(self.outcome.chunks.len() as u64).to_le_bytes());
+        for k in self.outcome.chunks.iter() {
+            b.extend_from_slice(&k.to_bytes());
+        }
+        b
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String>
+    {
+        let mut r = Reader{bytes, pos: 0};
+        if r.take(4)? != MAGIC {return Err("not a recording".to_string());}
+        let version = r.u32()?;
+        if version != VERSION {return Err(format!("unsupported recording version {}", version));}
+        let mut rec = Recording::new(r.u32()?, r.f64()?);
+        // checked before reserving, a corrupt count would otherwise abort on the allocation
+        let count = r.u64()?;
+        if count > ((bytes.len() - r.pos) / TICK_BYTES) as u64 {
+            return Err(format!("recording truncated, {} ticks don't fit", count));
+        }
+        rec.ticks.reserve(count as usize);
+        for _ in 0 .. count {
+            let held = r.u32()?;
+            let pressed = r.u32()?;
+            let mut axes = [0.0; AXIS_COUNT];
+            for a in axes.iter_mut() {
+                *a = r.f64()?;
+            }
+            rec.ticks.push(InputState {axes, held, pressed});
+        }
+        rec.outcome.position = dvec3(r.f64()?, r.f64()?, r.f64()?);
+        let chunks = r.u64()? as usize;
+        for _ in 0 .. chunks {
+            let mut key = [0u8; 12];
+            key.copy_from_slice(r.take(12)?);
+            rec.outcome.chunks.push(ChunkKey::from_bytes(key));
+        }
+        Ok(rec)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String>
+    {
+        std::fs::write(path, self.to_bytes()).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn load(path: &str) -> Result<Self, String>
+    {
+        let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::from_bytes(&bytes)
+    }
+
+    // runs the recorded ticks on a fresh game, no window or gpu
+    pub fn replay(&self) -> Result<ReplayOutcome, String>
+    {
+        let mut game = Game::with_seed(self.seed);
+        game.initialize()?;
+        let dt = (1.0 / self.tick_rate) as f32;
+        for tick in self.ticks.iter() {
+            game.update(dt, tick)?;
+        }
+        Ok(ReplayOutcome::from_game(&game))
+    }
+
+    // replay and check against the recorded outcome
+    pub fn verify(&self) -> Result<ReplayOutcome, String>
+    {
+        let outcome = self.replay()?;
+        self.outcome.compare(&outcome)?;
+        Ok(outcome)
+    }
+
+}
+
+struct Reader<'a>
+{
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a>
+{
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String>
+    {
+        if self.pos + n > self.bytes.len() {return Err("recording truncated".to_string());}
+        let s = &self.bytes[self.pos .. self.pos + n];
+        self.pos += n;
+        Ok(s)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> { Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap())) }
+    fn u64(&mut self) -> Result<u64, String> { Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap())) }
+    fn f64(&mut self) -> Result<f64, String> { Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap())) }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::headless::{HeadlessRunner, parse_script};
+
+    const SCRIPT: &str = "\
+30 move_z=1
+30 move_x=0.5 move_y=-0.2
+";
+
+    fn record(seed: u32, ticks: &[InputState]) -> Recording
+    {
+        let mut runner = HeadlessRunner::new(seed, 60.0).unwrap();
+        runner.run(ticks).unwrap();
+        let mut rec = Recording::new(seed, 60.0);
+        for t in ticks.iter() {
+            rec.push(t);
+        }
+        rec.finish(&runner.game);
+        rec
+    }
+
+    fn replay(rec: &Recording) -> ReplayOutcome
+    {
+        let mut runner = HeadlessRunner::new(rec.seed, rec.tick_rate).unwrap();
+        runner.run(&rec.ticks).unwrap();
+        ReplayOutcome::from_game(&runner.game)
+    }
+
+    #[test]
+    fn round_trip_replays()
+    {
+        let rec = record(7, &parse_script(SCRIPT).unwrap());
+        let loaded = Recording::from_bytes(&rec.to_bytes()).unwrap();
+        assert_eq!(loaded, rec);
+        loaded.outcome.compare(&replay(&loaded)).unwrap();
+    }
+
+    #[test]
+    fn changed_input_fails()
+    {
+        let rec = record(7, &parse_script(SCRIPT).unwrap());
+        let mut changed = rec.clone();
+        changed.ticks[10].axes[crate::input::Axis::MoveZ as usize] = -1.0;
+        assert!(rec.outcome.compare(&replay(&changed)).is_err());
+    }
+
+    #[test]
+    fn corrupt_tick_count_is_rejected()
+    {
+        let mut bytes = record(7, &parse_script("1 move_z=1").unwrap()).to_bytes();
+        // count sits after magic, version, seed and tick_rate
+        bytes[20 .. 28].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(Recording::from_bytes(&bytes).is_err());
+        assert!(Recording::from_bytes(&bytes[.. 24]).is_err());
+    }
+}
@@ -15,13 +15,36 @@ use crate::{
 pub mod chunk;
 //pub mod bobbins;
 pub mod sdftest;
+pub mod query;
+
+use query::SurfaceHit;
+use chunk::{StageTimings, ChunkStats};
 
 pub trait World {
     fn new() -> Self where Self: Sized;
     fn initialize(&mut self);
     fn update(&mut self, player: &Player);
-    fn get_meshes(&self) -> (Vec<(SeaHashKey, &IndexedMesh)>, &SeaHashSet<SeaHashKey>) {panic!("Meshes Not Implemented")}
+    fn get_meshes(&self) -> (Vec<(ChunkKey, &IndexedMesh)>, &SeaHashSet<ChunkKey>) {panic!("Meshes Not Implemented")}
     fn get_data(&self) -> Vec<u8> {panic!("Data Not Implemented")}
+    fn seed(&self) -> u32 {panic!("Seed Not Implemented")}
+    fn chunk_keys(&self) -> Vec<ChunkKey> {panic!("Chunk Keys Not Implemented")}
+    fn memory_usage(&self) -> usize {panic!("Memory Usage Not Implemented")}
+    // once per rendered frame, after get_meshes was consumed
+    fn end_frame(&mut self) -> StageTimings {panic!("End Frame Not Implemented")}
+    fn chunk_stats(&self) -> ChunkStats {panic!("Stats Not Implemented")}
+    fn current_chunk(&self) -> IVec3 {panic!("Current Chunk Not Implemented")}
+    // world units per chunk edge
+    fn chunk_extent(&self) -> f64 {panic!("Chunk Extent Not Implemented")}
+    // terrain field, negative is solid
+    fn sample_value(&self, pos: DVec3) -> f64 {panic!("Sampling Not Implemented")}
+    fn sample_normal(&self, pos: DVec3) -> DVec3 {panic!("Sampling Not Implemented")}
+    fn march(&self, origin: DVec3, dir: DVec3, max_dist: f64) -> Option<f64> {panic!("Sampling Not Implemented")}
+    // spatial queries
+    fn raycast(&self, origin: DVec3, dir: DVec3, max_dist: f64) -> Option<SurfaceHit> {panic!("Queries Not Implemented")}
+    fn sphere_cast(&self, origin: DVec3, dir: DVec3, radius: f64, max_dist: f64) -> Option<SurfaceHit> {panic!("Queries Not Implemented")}
+    fn sphere_contact(&self, center: DVec3, radius: f64) -> Option<(DVec3, DVec3, f64)> {panic!("Queries Not Implemented")}
+    fn altitude(&self, pos: DVec3, max_dist: f64) -> Option<f64> {panic!("Queries Not Implemented")}
+    fn closest_surface(&self, pos: DVec3, max_dist: f64) -> Option<SurfaceHit> {panic!("Queries Not Implemented")}
 }
 
 pub trait WorldObject {
@@ -14,6 +14,7 @@ use crate::{
     world::*,
     render::*,
 };
+use std::time::{Duration, Instant};
 use glam::*;
 
 //{{{ WorldChunk
@@ -235,6 +236,76 @@ impl WorldChunk {
 
 //}}}
 
+// StageTimings -- time spent in generate_chunks and its stages
+// accumulated over all ticks of a frame, reset by end_frame
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StageTimings
+{
+    pub generate: Duration,
+    pub create_chunk: Duration,
+    pub surface_map: Duration,
+    pub mesh: Duration,
+    pub chunks: u32,
+    pub surface_maps: u32,
+    pub meshes: u32,
+}
+
+impl StageTimings
+{
+    pub fn add(&mut self, other: &StageTimings)
+    {
+        self.generate += other.generate;
+        self.create_chunk += other.create_chunk;
+        self.surface_map += other.surface_map;
+        self.mesh += other.mesh;
+        self.chunks += other.chunks;
+        self.surface_maps += other.surface_maps;
+        self.meshes += other.meshes;
+    }
+}
+
+// ChunkStats -- live counters for the hud, logs and benchmarks
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ChunkStats
+{
+    // queues
+    pub queue_chunk: usize,
+    pub queue_sfp: usize,
+    pub queue_mesh: usize,
+    pub pending: usize,
+    // resident
+    pub chunks: usize,
+    pub surface_maps: usize,
+    pub meshes: usize,
+    pub sdf_nodes: usize,
+    pub surface_nodes: usize,
+    pub bytes: usize,
+    // average stage durations since start
+    pub avg_create_chunk: Duration,
+    pub avg_surface_map: Duration,
+    pub avg_mesh: Duration,
+    // last frame
+    pub frame: StageTimings,
+}
+
+impl std::fmt::Display for ChunkStats
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+    {
+        let us = |d: Duration| d.as_secs_f64() * 1e6;
+        write!(f,
+            "queues {}/{}/{} pending {} | resident {} chunks {} sfp {} meshes | nodes {} sdf {} sfp | {:.1} MiB | avg {:.0}/{:.0}/{:.0} us",
+            self.queue_chunk, self.queue_sfp, self.queue_mesh, self.pending,
+            self.chunks, self.surface_maps, self.meshes,
+            self.sdf_nodes, self.surface_nodes,
+            self.bytes as f64 / (1024.0 * 1024.0),
+            us(self.avg_create_chunk), us(self.avg_surface_map), us(self.avg_mesh),
+        )
+    }
+}
+
 // ChunkManager
 // is essentially a copy of bobbinsworld
 // decoupled from player
@@ -246,18 +317,21 @@ pub struct ChunkManager
     pub chunk_degree: u8,
     pub chunk_sample_scale: f64,
     pub chunk_scale: f64,
-    pub chunks: SeaHashMap<SeaHashKey, WorldChunk>,
-    pub surface_maps: SeaHashMap<SeaHashKey, SurfaceOctree>,
-    pub meshes: SeaHashMap<SeaHashKey, IndexedMesh>,
+    pub chunks: SeaHashMap<ChunkKey, WorldChunk>,
+    pub surface_maps: SeaHashMap<ChunkKey, SurfaceOctree>,
+    pub meshes: SeaHashMap<ChunkKey, IndexedMesh>,
     pub view_dist: i32,
     pub gen_dist: i32,
     pub operations_per_frame: i32,
     pub queue_chunk: Vec<IVec3>,
     pub queue_sfp: Vec<IVec3>,
     pub queue_mesh: Vec<IVec3>,
-    pub operation_pending: SeaHashSet<SeaHashKey>,
-    pub chunk_updated: SeaHashSet<SeaHashKey>,
+    pub operation_pending: SeaHashSet<ChunkKey>,
+    pub chunk_updated: SeaHashSet<ChunkKey>, // since the last end_frame
     pub distance_field: DistanceField,
+    pub timings: StageTimings, // since the last end_frame
+    pub totals: StageTimings,  // since start
+    pub last_frame: StageTimings,
 }
 
 impl ChunkManager
@@ -283,13 +357,43 @@ impl ChunkManager
             operation_pending: SeaHashSet::new(),
             chunk_updated: SeaHashSet::new(),
             distance_field: DistanceField::new(),
+            timings: Default::default(),
+            totals: Default::default(),
+            last_frame: Default::default(),
         }
     }
 
+    pub fn with_seed(seed: u32) -> Self
+    {
+        Self {distance_field: DistanceField::with_seed(seed), ..Self::new()}
+    }
+
+    // generated chunks in a stable order, for comparing runs
+    pub fn chunk_keys(&self) -> Vec<ChunkKey> {
+        let mut keys: Vec<ChunkKey> = self.chunks.keys().copied().collect();
+        keys.sort_by_key(|k| k.coord().to_array());
+        keys
+    }
+
+    // rough resident size in bytes, map overhead not counted
+    pub fn memory_usage(&self) -> usize {
+        use std::mem::size_of;
+        let key = size_of::<ChunkKey>();
+        let chunks: usize = self.chunks.values()
+            .map(|c| key + size_of::<WorldChunk>() + c.sdftree.values.len() * (8 + size_of::<SDFNode>()))
+            .sum();
+        let surfaces: usize = self.surface_maps.values()
+            .map(|s| key + size_of::<SurfaceOctree>() + s.values.len() * (8 + size_of::<SurfaceNode>()))
+            .sum();
+        let meshes: usize = self.meshes.values()
+            .map(|m| key + size_of::<IndexedMesh>() + m.memory_usage() + m.vert_index.len() * (size_of::<VoxelKey>() + 8))
+            .sum();
+        chunks + surfaces + meshes
+    }
+
     #[inline]
-    pub fn chunk_coord2key(&self, coord: IVec3) -> SeaHashKey {
-        let coord = coord * self.chunk_size;
-        coord2key(coord)
+    pub fn chunk_coord2key(&self, coord: IVec3) -> ChunkKey {
+        ChunkKey::new(coord)
     }
 
     pub fn create_chunk(&mut self, chunk_coord: IVec3)
@@ -311,6 +415,8 @@ impl ChunkManager
 
     pub fn generate_chunks(&mut self, cur_chunk: IVec3)
     {
+        let start = Instant::now();
+        let mut call = StageTimings::default();
         let mut do_generation = false;
         // check for non-visible chunks
         let coords = Self::nearby_coords(cur_chunk, self.view_dist);
@@ -336,13 +442,15 @@ impl ChunkManager
             }
         }
 
-        self.chunk_updated.clear();
         let mut chunk_updated_list = vec![];
         for i in 0 .. self.operations_per_frame {
             if ! self.queue_chunk.is_empty()
             {
                 let c = self.queue_chunk.pop().unwrap();
+                let t = Instant::now();
                 self.create_chunk(c);
+                call.create_chunk += t.elapsed();
+                call.chunks += 1;
                 // regen surrounding sfp+mesh
                 for dir in IDirection::NEGATIVE_DIRS
                 {
@@ -361,7 +469,10 @@ impl ChunkManager
             else if ! self.queue_sfp.is_empty()
             {
                 let c = self.queue_sfp.pop().unwrap();
+                let t = Instant::now();
                 self.create_surface_map(c);
+                call.surface_map += t.elapsed();
+                call.surface_maps += 1;
                 // regen surrounding mesh
                 for dir in IDirection::POSITIVE_DIRS
                 {
@@ -380,13 +491,49 @@ impl ChunkManager
             else if ! self.queue_mesh.is_empty()
             {
                 let c = self.queue_mesh.pop().unwrap();
+                let t = Instant::now();
                 self.create_mesh(c);
+                call.mesh += t.elapsed();
+                call.meshes += 1;
                 let key = self.chunk_coord2key(c);
                 self.operation_pending.remove(&key);
                 self.chunk_updated.insert(key);
                 chunk_updated_list.push(key);
             }
         }
+        call.generate = start.elapsed();
+        self.timings.add(&call);
+        self.totals.add(&call);
+    }
+
+    // the renderer has seen this frame's meshes, returns the frame's timings
+    // several ticks can run per frame, their updates are kept until then
+    pub fn end_frame(&mut self) -> StageTimings
+    {
+        self.chunk_updated.clear();
+        self.last_frame = std::mem::take(&mut self.timings);
+        self.last_frame
+    }
+
+    pub fn stats(&self) -> ChunkStats
+    {
+        let avg = |d: Duration, n: u32| if n > 0 {d / n} else {Duration::ZERO};
+        ChunkStats {
+            queue_chunk: self.queue_chunk.len(),
+            queue_sfp: self.queue_sfp.len(),
+            queue_mesh: self.queue_mesh.len(),
+            pending: self.operation_pending.len(),
+            chunks: self.chunks.len(),
+            surface_maps: self.surface_maps.len(),
+            meshes: self.meshes.len(),
+            sdf_nodes: self.chunks.values().map(|c| c.sdftree.values.len()).sum(),
+            surface_nodes: self.surface_maps.values().map(|s| s.values.len()).sum(),
+            bytes: self.memory_usage(),
+            avg_create_chunk: avg(self.totals.create_chunk, self.totals.chunks),
+            avg_surface_map: avg(self.totals.surface_map, self.totals.surface_maps),
+            avg_mesh: avg(self.totals.mesh, self.totals.meshes),
+            frame: self.last_frame,
+        }
     }
 
     // assume chunk exists
@@ -522,7 +669,7 @@ impl ChunkManager
         let chunk = self.chunks.get(&chunk_key).unwrap();
         let neighbors = &self.get_neighbor_chunks(chunk_coord, IDirection::UNIT_DIRS);
         let n_neighbors = &self.get_neighbor_chunks(chunk_coord, IDirection::NEGATIVE_DIRS);
-        let mut mesh = IndexedMesh::new();
+        let mut mesh = IndexedMesh::for_chunk(chunk_coord, (self.chunk_size as f64 * self.chunk_scale) as f32);
         let mesh_position = to_dvec3(chunk.coord * (1 << chunk.degree));
         for loc in self.get_surface_map(chunk_coord).keys()
         {
@@ -556,9 +703,101 @@ impl ChunkManager
                 }
             }
         }
+        mesh.shrink_to_fit();
         self.meshes.insert(chunk_key, mesh);
     }
 
+    // field sampling {{{
+
+    // bound on the gradient of the trilinear field per voxel
+    // neighbouring samples differ by at most the decompressed span,
+    // decompress(255) - decompress(0) = 255 / 64, sqrt(3) of that along a diagonal
+    pub const FIELD_LIPSCHITZ: f64 = 1.7320508075688772 * 255.0 / 64.0;
+    // smallest march step in voxels, keeps marching moving near the surface
+    pub const MIN_MARCH_STEP: f64 = 0.05;
+
+    // field value at an absolute voxel coordinate, negative is solid
+    // reads chunk data when loaded, otherwise evaluates the field directly
+    // chunks sample at (coord - midpoint), mirrored here so both agree
+    pub fn voxel_value(&self, voxel: IVec3) -> f64 {
+        let chunk_coord = ivec3(
+            floor_div(voxel.x, self.chunk_size),
+            floor_div(voxel.y, self.chunk_size),
+            floor_div(voxel.z, self.chunk_size),
+        );
+        match self.chunks.get(&self.chunk_coord2key(chunk_coord)) {
+            Some(chunk) => DistanceField::decompress(
+                chunk.get_voxel_by_coord(voxel - chunk_coord * self.chunk_size)
+            ),
+            None => {
+                let mp_ax = if self.chunk_degree > 0 {1 << (self.chunk_degree - 1)} else {0};
+                let pos = to_dvec3(voxel - ivec3(mp_ax, mp_ax, mp_ax)) * self.chunk_sample_scale;
+                DistanceField::decompress(self.distance_field.gen(pos))
+            }
+        }
+    }
+
+    // trilinear field value at a world position, negative is solid
+    pub fn sample_value(&self, pos: DVec3) -> f64 {
+        let p = pos / self.chunk_scale;
+        let base = p.floor();
+        let t = p - base;
+        let base = base.as_ivec3();
+        let mut c = [0.0; 8];
+        for i in 0 .. 8 {
+            let o = ivec3(i & 1, (i >> 1) & 1, (i >> 2) & 1);
+            c[i as usize] = self.voxel_value(base + o);
+        }
+        let x00 = c[0] + (c[1] - c[0]) * t.x;
+        let x10 = c[2] + (c[3] - c[2]) * t.x;
+        let x01 = c[4] + (c[5] - c[4]) * t.x;
+        let x11 = c[6] + (c[7] - c[6]) * t.x;
+        let y0 = x00 + (x10 - x00) * t.y;
+        let y1 = x01 + (x11 - x01) * t.y;
+        y0 + (y1 - y0) * t.z
+    }
+
+    // points out of the solid, zero where the field is flat
+    pub fn sample_normal(&self, pos: DVec3) -> DVec3 {
+        let h = 0.5 * self.chunk_scale;
+        let grad = dvec3(
+            self.sample_value(pos + DDirection::RIGHT * h) - self.sample_value(pos + DDirection::LEFT * h),
+            self.sample_value(pos + DDirection::UP * h) - self.sample_value(pos + DDirection::DOWN * h),
+            self.sample_value(pos + DDirection::FORWARD * h) - self.sample_value(pos + DDirection::BACK * h),
+        );
+        grad.normalize_or_zero()
+    }
+
+    // sphere trace along dir (normalized) for the first sign change of the field
+    // steps are bounded by FIELD_LIPSCHITZ so no crossing is skipped, then bisected
+    // returns distance along the ray
+    pub fn march(&self, origin: DVec3, dir: DVec3, max_dist: f64) -> Option<f64> {
+        let min_step = Self::MIN_MARCH_STEP * self.chunk_scale;
+        let mut t = 0.0;
+        let mut v = self.sample_value(origin);
+        let start_solid = v < 0.0;
+        while t < max_dist {
+            let step = (v.abs() / Self::FIELD_LIPSCHITZ * self.chunk_scale).max(min_step);
+            let t_next = (t + step).min(max_dist);
+            let v_next = self.sample_value(origin + dir * t_next);
+            if (v_next < 0.0) != start_solid {
+                // refine crossing between t and t_next
+                let (mut a, mut b) = (t, t_next);
+                for _ in 0 .. 10 {
+                    let m = 0.5 * (a + b);
+                    if (self.sample_value(origin + dir * m) < 0.0) != start_solid {b = m;}
+                    else {a = m;}
+                }
+                return Some(b);
+            }
+            t = t_next;
+            v = v_next;
+        }
+        None
+    }
+
+    // }}}
+
     pub fn nearby_coords(orig: IVec3, dist: i32) -> Vec<IVec3>
     {
         let s = (2 * dist + 1) * (2 * dist + 1) * (2 * dist + 1);
@@ -610,7 +849,7 @@ impl ChunkManager
         coords
     }
 
-    pub fn visible_meshes(&self, cur_chunk: IVec3) -> Vec<(SeaHashKey, &IndexedMesh)>
+    pub fn visible_meshes(&self, cur_chunk: IVec3) -> Vec<(ChunkKey, &IndexedMesh)>
     {
         let coords = Self::nearby_coords(cur_chunk, self.view_dist);
         let mut ret = Vec::with_capacity(10 * self.view_dist as usize);
@@ -0,0 +1,145 @@
+use glam::*;
+use crate::math::{*,
+    hasher::*,
+    direction::*,
+    generator::DistanceField,
+};
+use super::chunk::ChunkManager;
+
+// Spatial queries against the terrain field
+// work on loaded chunks and fall back to the distance field (see voxel_value)
+
+#[derive(Clone, Copy, Debug)]
+pub struct SurfaceHit {
+    pub position: DVec3,
+    pub normal: DVec3,
+    pub distance: f64,  // along the ray, or from the query point
+    pub chunk: ChunkKey,
+    pub loaded: bool,   // hit chunk is resident (otherwise from the field directly)
+    pub material: u8,
+}
+
+const D: f64 = 0.577_350_269_189_625_8; // 1 / sqrt(3)
+
+// axes and diagonals, used to probe around a point
+pub const PROBE_DIRS: [DVec3; 14] = [
+    DDirection::RIGHT, DDirection::LEFT,
+    DDirection::UP, DDirection::DOWN,
+    DDirection::FORWARD, DDirection::BACK,
+    DVec3{x: D, y: D, z: D}, DVec3{x:-D, y: D, z: D},
+    DVec3{x: D, y:-D, z: D}, DVec3{x:-D, y:-D, z: D},
+    DVec3{x: D, y: D, z:-D}, DVec3{x:-D, y: D, z:-D},
+    DVec3{x: D, y:-D, z:-D}, DVec3{x:-D, y:-D, z:-D},
+];
+
+impl ChunkManager
+{
+
+    pub fn pos2chunk_key(&self, pos: DVec3) -> ChunkKey {
+        let size = self.chunk_scale * self.chunk_size as f64;
+        self.chunk_coord2key((pos / size).floor().as_ivec3())
+    }
+
+    pub fn surface_hit(&self, position: DVec3, distance: f64) -> SurfaceHit {
+        let normal = self.sample_normal(position);
+        let chunk = self.pos2chunk_key(position);
+        SurfaceHit {
+            position,
+            normal,
+            distance,
+            chunk,
+            loaded: self.chunks.contains_key(&chunk),
+            material: DistanceField::material(normal),
+        }
+    }
+
+    // dir need not be normalized
+    pub fn raycast(&self, origin: DVec3, dir: DVec3, max_dist: f64) -> Option<SurfaceHit> {
+        let dir = dir.normalize_or_zero();
+        if dir == DVec3::ZERO {return None;}
+        let t = self.march(origin, dir, max_dist)?;
+        Some(self.surface_hit(origin + dir * t, t))
+    }
+
+    // deepest contact of a sphere as (surface point, normal out of the terrain, depth)
+    pub fn sphere_contact(&self, center: DVec3, radius: f64) -> Option<(DVec3, DVec3, f64)> {
+        let mut best: Option<(DVec3, DVec3, f64)> = None;
+        for dir in PROBE_DIRS.iter() {
+            let t = match self.march(center, *dir, radius) {
+                None => {continue;}
+                Some(t) => t,
+            };
+            let depth = radius - t;
+            if best.is_none_or(|(_, _, d)| depth > d) {
+                let point = center + *dir * t;
+                let mut normal = self.sample_normal(point);
+                if normal.dot(*dir) >= 0.0 { normal = -*dir; }
+                best = Some((point, normal, depth));
+            }
+        }
+        best
+    }
+
+    // first position along the ray where the sphere touches the terrain
+    // distance is how far the center travelled, position is the touched surface point
+    pub fn sphere_cast(&self, origin: DVec3, dir: DVec3, radius: f64, max_dist: f64) -> Option<SurfaceHit> {
+        let dir = dir.normalize_or_zero();
+        if dir == DVec3::ZERO {return None;}
+        if let Some((point, _, _)) = self.sphere_contact(origin, radius) {
+            return Some(self.surface_hit(point, 0.0));
+        }
+        let step = (radius * 0.5).max(Self::MIN_MARCH_STEP * self.chunk_scale);
+        let mut t = 0.0;
+        while t < max_dist {
+            let t_next = (t + step).min(max_dist);
+            if self.sphere_contact(origin + dir * t_next, radius).is_some() {
+                let (mut a, mut b) = (t, t_next);
+                for _ in 0 .. 8 {
+                    let m = 0.5 * (a + b);
+                    if self.sphere_contact(origin + dir * m, radius).is_some() {b = m;}
+                    else {a = m;}
+                }
+                let (point, _, _) = self.sphere_contact(origin + dir * b, radius).unwrap();
+                return Some(self.surface_hit(point, b));
+            }
+            t = t_next;
+        }
+        None
+    }
+
+    // height above the terrain directly below, zero inside the terrain
+    pub fn altitude(&self, pos: DVec3, max_dist: f64) -> Option<f64> {
+        if self.sample_value(pos) < 0.0 {return Some(0.0);}
+        self.march(pos, DDirection::DOWN, max_dist)
+    }
+
+    // nearest surface point within max_dist
+    // probes for a starting point then projects onto the surface along the gradient
+    pub fn closest_surface(&self, pos: DVec3, max_dist: f64) -> Option<SurfaceHit> {
+        let mut best: Option<f64> = None;
+        let mut point = pos;
+        for dir in PROBE_DIRS.iter() {
+            let limit = best.unwrap_or(max_dist);
+            if let Some(t) = self.march(pos, *dir, limit) {
+                best = Some(t);
+                point = pos + *dir * t;
+            }
+        }
+        best?;
+        let h = 0.5 * self.chunk_scale;
+        for _ in 0 .. 4 {
+            let v = self.sample_value(point);
+            let normal = self.sample_normal(point);
+            if normal == DVec3::ZERO {break;}
+            let grad = (
+                self.sample_value(point + normal * h) - self.sample_value(point - normal * h)
+            ) / (2.0 * h);
+            if grad.abs() < 1e-9 {break;}
+            let next = point - normal * (v / grad);
+            if next.distance(pos) > max_dist {break;}
+            point = next;
+        }
+        Some(self.surface_hit(point, point.distance(pos)))
+    }
+
+}
@@ -11,6 +11,15 @@ pub struct SdfWorld
     pub coord_last: IVec3,
 }
 
+impl SdfWorld {
+    pub fn with_seed(seed: u32) -> Self {
+        Self {
+            chunks: ChunkManager::with_seed(seed),
+            ..Self::new()
+        }
+    }
+}
+
 impl World for SdfWorld {
     fn new() -> Self where Self: Sized {
         Self {
@@ -27,13 +36,13 @@ impl World for SdfWorld {
     fn update(&mut self, player: &Player) {
         self.coord_cur = pos2chunk(player.get_position(), self.chunks.chunk_size);
         if self.coord_cur != self.coord_last {
-            println!("chunk {} {} {}", self.coord_cur.x, self.coord_cur.y, self.coord_cur.z);
+            log::debug!("chunk {} {} {}", self.coord_cur.x, self.coord_cur.y, self.coord_cur.z);
         }
         self.chunks.generate_chunks(self.coord_cur);
         self.coord_last = self.coord_cur;
     }
 
-    fn get_meshes(&self) -> (Vec<(SeaHashKey, &IndexedMesh)>, &SeaHashSet<SeaHashKey>) {
+    fn get_meshes(&self) -> (Vec<(ChunkKey, &IndexedMesh)>, &SeaHashSet<ChunkKey>) {
         let visible = self.chunks.visible_meshes(self.coord_cur);
         let updated = &self.chunks.chunk_updated;
         (visible, updated)
@@ -43,5 +52,65 @@ impl World for SdfWorld {
         Vec::<u8>::new()
     }
 
+    fn seed(&self) -> u32 {
+        self.chunks.distance_field.seed
+    }
+
+    fn chunk_keys(&self) -> Vec<ChunkKey> {
+        self.chunks.chunk_keys()
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.chunks.memory_usage()
+    }
+
+    fn end_frame(&mut self) -> StageTimings {
+        self.chunks.end_frame()
+    }
+
+    fn chunk_stats(&self) -> ChunkStats {
+        self.chunks.stats()
+    }
+
+    fn current_chunk(&self) -> IVec3 {
+        self.coord_cur
+    }
+
+    fn chunk_extent(&self) -> f64 {
+        self.chunks.chunk_size as f64 * self.chunks.chunk_scale
+    }
+
+    fn sample_value(&self, pos: DVec3) -> f64 {
+        self.chunks.sample_value(pos)
+    }
+
+    fn sample_normal(&self, pos: DVec3) -> DVec3 {
+        self.chunks.sample_normal(pos)
+    }
+
+    fn march(&self, origin: DVec3, dir: DVec3, max_dist: f64) -> Option<f64> {
+        self.chunks.march(origin, dir, max_dist)
+    }
+
+    fn raycast(&self, origin: DVec3, dir: DVec3, max_dist: f64) -> Option<SurfaceHit> {
+        self.chunks.raycast(origin, dir, max_dist)
+    }
+
+    fn sphere_cast(&self, origin: DVec3, dir: DVec3, radius: f64, max_dist: f64) -> Option<SurfaceHit> {
+        self.chunks.sphere_cast(origin, dir, radius, max_dist)
+    }
+
+    fn sphere_contact(&self, center: DVec3, radius: f64) -> Option<(DVec3, DVec3, f64)> {
+        self.chunks.sphere_contact(center, radius)
+    }
+
+    fn altitude(&self, pos: DVec3, max_dist: f64) -> Option<f64> {
+        self.chunks.altitude(pos, max_dist)
+    }
+
+    fn closest_surface(&self, pos: DVec3, max_dist: f64) -> Option<SurfaceHit> {
+        self.chunks.closest_surface(pos, max_dist)
+    }
+
 }
 